@@ -1,5 +1,4 @@
-use burst::{BurstBuilder, Machine, MachineSetup};
-use std::collections::HashMap;
+use burst::{BurstBuilder, MachineSetup};
 use tokio::runtime::Runtime;
 
 fn main() {
@@ -29,7 +28,7 @@ fn main() {
     let rt = Runtime::new().unwrap();
     rt.block_on(async {
         builder
-            .run(|vms: HashMap<String, Vec<Machine>>| {
+            .run(|vms| {
                 println!("server private ip: {}", vms["server"][0].private_ip);
                 println!("client private ip: {}", vms["client"][0].private_ip);
                 // let server = &vms["server"][0].private_ip;
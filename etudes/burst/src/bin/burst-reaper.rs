@@ -0,0 +1,95 @@
+//!Deletes the resources left behind by burst runs which died before their teardown.
+//!
+//!```text
+//!burst-reaper [--older-than HOURS] [--region REGION] [--profile PROFILE] [--dry-run]
+//!```
+//!
+//!Only the resources of runs which started at least `--older-than` hours ago, 24 by default,
+//!are touched, and clusters kept alive are left until they expire. With `--dry-run` they are
+//!listed without being deleted.
+use burst::Region;
+use burst::provider::Ec2;
+use slog::{Drain, Logger, o};
+use std::process::ExitCode;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+
+struct Options {
+    older_than: Duration,
+    region: Region,
+    profile: Option<String>,
+    dry_run: bool,
+}
+
+fn parse(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        older_than: Duration::from_secs(24 * 60 * 60),
+        region: Region::UsEast1,
+        profile: None,
+        dry_run: false,
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--older-than" => {
+                let hours: u64 = value()?
+                    .parse()
+                    .map_err(|e| format!("--older-than: {}", e))?;
+                options.older_than = Duration::from_secs(hours * 60 * 60);
+            }
+            "--region" => {
+                options.region = value()?.parse().map_err(|e| format!("--region: {}", e))?;
+            }
+            "--profile" => options.profile = Some(value()?),
+            "--dry-run" => options.dry_run = true,
+            _ => return Err(format!("unknown argument {}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+fn main() -> ExitCode {
+    let options = match parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!(
+                "usage: burst-reaper [--older-than HOURS] [--region REGION] [--profile PROFILE] [--dry-run]"
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let decorator = slog_term::TermDecorator::new().build();
+    let drain = Mutex::new(slog_term::FullFormat::new(decorator).build()).fuse();
+    let logger = Logger::root(drain, o!());
+
+    let ec2 = match &options.profile {
+        Some(profile) => Ec2::with_profile(options.region.clone(), profile),
+        None => Ok(Ec2::new(options.region.clone())),
+    };
+    let rt = Runtime::new().expect("failed to start the tokio runtime");
+    let result = ec2.and_then(|ec2| {
+        rt.block_on(async {
+            if options.dry_run {
+                burst::leftovers(&ec2, options.older_than).await
+            } else {
+                burst::reap(&ec2, options.older_than, &logger).await
+            }
+        })
+    });
+
+    match result {
+        Ok(resources) => {
+            for resource in resources {
+                println!("{}\t{:?}\t{}", resource.run_id, resource.kind, resource.id);
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
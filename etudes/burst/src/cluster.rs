@@ -0,0 +1,145 @@
+use crate::Machine;
+use crate::report::Recorder;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+///How many machines of a setup were asked for and how many were obtained.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fulfillment {
+    ///the number of machines passed to `add_setup`
+    pub requested: i64,
+    ///the number of machines which were launched
+    pub obtained: i64,
+    ///how many times the missing machines were requested again
+    pub retries: usize,
+}
+
+///The machines of a burst, by setup name, as handed to the main script.
+///
+///It dereferences to the map of machines, so `vms["server"][0]` works as before, and also tells
+///how each setup was fulfilled.
+pub struct Cluster {
+    machines: HashMap<String, Vec<Machine>>,
+    fulfillment: HashMap<String, Fulfillment>,
+    recorder: Recorder,
+}
+
+impl Cluster {
+    pub(crate) fn new(
+        machines: HashMap<String, Vec<Machine>>,
+        fulfillment: HashMap<String, Fulfillment>,
+        recorder: Recorder,
+    ) -> Self {
+        Self {
+            machines,
+            fulfillment,
+            recorder,
+        }
+    }
+
+    ///Record a metric of the whole run in its report, such as the throughput summed over the
+    ///clients; the metrics of a single machine go through [`Machine::record`].
+    pub fn record(&self, name: &str, value: f64) {
+        self.recorder.record(name, value, None);
+    }
+
+    ///How the setup with the given name was fulfilled.
+    pub fn fulfillment(&self, name: &str) -> Option<&Fulfillment> {
+        self.fulfillment.get(name)
+    }
+
+    ///The private ips of the machines of the setup with the given name.
+    pub fn private_ips(&self, name: &str) -> Vec<&str> {
+        self.machines
+            .get(name)
+            .into_iter()
+            .flatten()
+            .map(|machine| machine.private_ip.as_str())
+            .collect()
+    }
+
+    pub(crate) fn instance_ids(&self) -> Vec<String> {
+        self.machines
+            .values()
+            .flatten()
+            .map(|machine| machine.instance_id.clone())
+            .collect()
+    }
+
+    ///Set aside the machines of each of the given setups beyond the given number, and return
+    ///them for [`put_back`](Self::put_back).
+    pub(crate) fn set_aside(
+        &mut self,
+        counts: &BTreeMap<String, usize>,
+    ) -> Result<HashMap<String, Vec<Machine>>, Shortfall> {
+        for (name, count) in counts {
+            let obtained = self.machines.get(name).map_or(0, Vec::len);
+            if obtained < *count {
+                return Err(Shortfall {
+                    name: name.clone(),
+                    fulfillment: Fulfillment {
+                        requested: *count as i64,
+                        obtained: obtained as i64,
+                        retries: self.fulfillment.get(name).map_or(0, |f| f.retries),
+                    },
+                });
+            }
+        }
+        Ok(counts
+            .iter()
+            .filter_map(|(name, count)| {
+                let group = self.machines.get_mut(name)?;
+                Some((name.clone(), group.split_off(*count)))
+            })
+            .collect())
+    }
+
+    ///Return the machines set aside by [`set_aside`](Self::set_aside).
+    pub(crate) fn put_back(&mut self, aside: HashMap<String, Vec<Machine>>) {
+        for (name, machines) in aside {
+            self.machines.entry(name).or_default().extend(machines);
+        }
+    }
+
+    ///Take the machines out of the cluster.
+    pub fn into_machines(self) -> HashMap<String, Vec<Machine>> {
+        self.machines
+    }
+}
+
+impl Deref for Cluster {
+    type Target = HashMap<String, Vec<Machine>>;
+    fn deref(&self) -> &Self::Target {
+        &self.machines
+    }
+}
+
+impl DerefMut for Cluster {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.machines
+    }
+}
+
+///The error `run` fails with when a setup did not get enough machines for its
+///[`ShortfallPolicy`](crate::ShortfallPolicy).
+#[derive(Debug)]
+pub struct Shortfall {
+    ///the name of the setup
+    pub name: String,
+    ///the fulfillment of the setup
+    pub fulfillment: Fulfillment,
+}
+
+impl fmt::Display for Shortfall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "only {} of the {} machines requested for {} could be launched",
+            self.fulfillment.obtained, self.fulfillment.requested, self.name
+        )
+    }
+}
+
+impl std::error::Error for Shortfall {}
@@ -0,0 +1,210 @@
+use crate::provider::Provider;
+use crate::{Error, MachineSetup, PurchaseOption};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+///Where the hourly prices of the instance types come from, to estimate what a burst costs before
+///launching it; see [`BurstBuilder::set_budget`](crate::BurstBuilder::set_budget).
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum PriceTable {
+    ///The current spot prices the provider reports, the highest over the availability zones. The
+    ///spot market does not tell what on-demand instances cost, so setups which may buy them need
+    ///one of the other tables.
+    #[default]
+    SpotHistory,
+    ///The given hourly prices in USD, by instance type.
+    Static(HashMap<String, f64>),
+    ///The hourly prices in USD stored in the given JSON file as an object from instance types to
+    ///prices, such as `{"c5.large": 0.085}`, for estimating without asking the provider.
+    File(PathBuf),
+}
+
+impl PriceTable {
+    ///The hourly price of each of the given instance types, for machines bought as `on_demand`
+    ///says.
+    async fn prices<P: Provider>(
+        &self,
+        provider: &P,
+        instance_types: &[String],
+        on_demand: bool,
+    ) -> Result<HashMap<String, f64>, Error> {
+        match self {
+            PriceTable::SpotHistory if on_demand => Err(Error::Prices(
+                "the spot price history does not tell the price of on-demand instances".to_string(),
+            )),
+            PriceTable::SpotHistory => provider.spot_prices(instance_types).await,
+            PriceTable::Static(prices) => Ok(prices.clone()),
+            PriceTable::File(path) => load(path),
+        }
+    }
+}
+
+fn load(path: &Path) -> Result<HashMap<String, f64>, Error> {
+    let json = std::fs::read_to_string(path)
+        .map_err(|e| Error::io(format!("read the price file {}", path.display()), e))?;
+    serde_json::from_str(&json).map_err(|e| {
+        Error::Prices(format!(
+            "the price file {} is invalid: {}",
+            path.display(),
+            e
+        ))
+    })
+}
+
+///What the machines of a setup cost.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SetupCost {
+    ///the instance type
+    pub instance_type: String,
+    ///the number of machines
+    pub machines: i64,
+    ///the hourly price of a machine in USD
+    pub price: f64,
+}
+
+///What a burst is expected to cost, in USD.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Estimate {
+    ///the cost of each setup, by name
+    pub setups: BTreeMap<String, SetupCost>,
+    ///the cost of an hour of the whole cluster
+    pub hourly: f64,
+    ///the cost of the cluster running for the maximum duration of the burst, which the machines
+    ///shut themselves down after
+    pub max_duration: f64,
+}
+
+///Estimate what the given setups cost with the prices of the table, running for `duration`.
+pub(crate) async fn estimate<'a, P: Provider>(
+    provider: &P,
+    table: &PriceTable,
+    setups: impl Iterator<Item = (&'a String, &'a MachineSetup, i64)>,
+    duration: Duration,
+) -> Result<Estimate, Error> {
+    let setups: Vec<_> = setups.collect();
+    let mut prices = HashMap::new();
+    for on_demand in [false, true] {
+        let instance_types: Vec<String> = setups
+            .iter()
+            .filter(|(_, setup, _)| buys_on_demand(setup) == on_demand)
+            .map(|(_, setup, _)| setup.instance_type.clone())
+            .collect();
+        if !instance_types.is_empty() {
+            let found = table.prices(provider, &instance_types, on_demand).await?;
+            prices.insert(on_demand, found);
+        }
+    }
+
+    let mut estimate = Estimate {
+        setups: BTreeMap::new(),
+        hourly: 0.0,
+        max_duration: 0.0,
+    };
+    for (name, setup, machines) in setups {
+        let price = prices
+            .get(&buys_on_demand(setup))
+            .and_then(|prices| prices.get(&setup.instance_type))
+            .copied()
+            .ok_or_else(|| {
+                Error::Prices(format!("no price is known for {}", setup.instance_type))
+            })?;
+        estimate.hourly += price * machines as f64;
+        estimate.setups.insert(
+            name.clone(),
+            SetupCost {
+                instance_type: setup.instance_type.clone(),
+                machines,
+                price,
+            },
+        );
+    }
+    estimate.max_duration = estimate.hourly * duration.as_secs_f64() / 3600.0;
+    Ok(estimate)
+}
+
+//Whether some machines of the setup may be on-demand instances.
+fn buys_on_demand(setup: &MachineSetup) -> bool {
+    match setup.purchase {
+        PurchaseOption::Spot { .. } => false,
+        PurchaseOption::OnDemand | PurchaseOption::SpotWithFallback { .. } => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::Fake;
+    use crate::testing::{builder, run_torn_down};
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn on_demand_machines_are_not_priced_from_the_spot_market() {
+        let fake = Fake::new().with_spot_price("c5.large", 0.04);
+        let spot = MachineSetup::new("c5.large", "ami-fake", |_| Ok(()));
+        let on_demand = MachineSetup::new("c5.large", "ami-fake", |_| Ok(()))
+            .with_purchase_option(PurchaseOption::OnDemand);
+        let (client, server) = ("client".to_string(), "server".to_string());
+        let hours = Duration::from_secs(2 * 60 * 60);
+
+        let rt = Runtime::new().unwrap();
+        let cost = rt
+            .block_on(estimate(
+                &fake,
+                &PriceTable::SpotHistory,
+                [(&client, &spot, 3)].into_iter(),
+                hours,
+            ))
+            .unwrap();
+        assert!((cost.hourly - 0.12).abs() < 1e-9);
+        assert!((cost.max_duration - 0.24).abs() < 1e-9);
+
+        let setups = [(&client, &spot, 3), (&server, &on_demand, 1)];
+        let refused = rt.block_on(estimate(
+            &fake,
+            &PriceTable::SpotHistory,
+            setups.into_iter(),
+            hours,
+        ));
+        assert!(matches!(refused, Err(Error::Prices(_))));
+
+        let table = PriceTable::Static([("c5.large".to_string(), 0.085)].into());
+        let cost = rt
+            .block_on(estimate(&fake, &table, setups.into_iter(), hours))
+            .unwrap();
+        assert_eq!(cost.setups["server"].price, 0.085);
+        assert!((cost.hourly - 0.34).abs() < 1e-9);
+    }
+
+    #[test]
+    fn bursts_over_budget_are_not_launched() {
+        let mut builder = builder(&[]);
+        builder.set_max_duration(Duration::from_secs(2 * 60 * 60));
+        builder.add_setup(
+            "client".to_string(),
+            10,
+            MachineSetup::new("c5.large", "ami-fake", |_| Ok(())),
+        );
+        builder.set_budget(1.0);
+        let fake = Fake::new().with_spot_price("c5.large", 0.1);
+
+        let result = run_torn_down(&fake, builder.run_with(&fake, |_| Ok(())));
+        match result {
+            Err(Error::OverBudget { estimate, budget }) => {
+                assert!((estimate.max_duration - 2.0).abs() < 1e-9);
+                assert_eq!(budget, 1.0);
+            }
+            other => panic!("the burst was not refused: {:?}", other),
+        }
+
+        builder.set_budget(2.5);
+        run_torn_down(
+            &fake,
+            builder.run_with(&fake, |vms| {
+                assert_eq!(vms["client"].len(), 10);
+                Ok(())
+            }),
+        )
+        .unwrap();
+    }
+}
@@ -0,0 +1,287 @@
+use crate::ssh::Output;
+use crate::{Estimate, Shortfall, Timeout};
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+///Any error, as returned by setup closures and the main script.
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+///The errors of a burst.
+#[derive(Debug)]
+pub enum Error {
+    ///A call to the cloud provider failed.
+    Provider {
+        ///what the provider was asked to do, such as `create the security group`
+        action: String,
+        ///the error of the provider
+        source: BoxError,
+    },
+    ///No ssh connection could be established to a machine.
+    Connect {
+        ///the address which was connected to
+        address: String,
+        ///why the connection failed
+        source: BoxError,
+    },
+    ///The host key of a machine could not be verified.
+    HostKey {
+        ///the address which was connected to
+        address: String,
+        ///why the key was refused
+        source: BoxError,
+    },
+    ///The machine refused the ssh credentials.
+    Auth {
+        ///the address which was connected to
+        address: String,
+        ///the user which tried to log in
+        username: String,
+        ///why the authentication failed
+        source: BoxError,
+    },
+    ///A command could not be run on a machine.
+    Command {
+        ///the command
+        command: String,
+        ///why it could not be run
+        source: BoxError,
+    },
+    ///A command exited with a non-zero status.
+    Exit {
+        ///the command
+        command: String,
+        ///what the command did
+        output: Output,
+    },
+    ///A file or directory could not be copied to or from a machine.
+    Transfer {
+        ///where it was copied from, prefixed with the machine address if it is remote
+        from: String,
+        ///where it was copied to, prefixed with the machine address if it is remote
+        to: String,
+        ///why the copy failed
+        source: BoxError,
+    },
+    ///A local file or runtime could not be set up.
+    Io {
+        ///what was being done, such as `store the private key`
+        action: String,
+        ///the io error
+        source: std::io::Error,
+    },
+    ///The error a setup closure returned.
+    Closure(BoxError),
+    ///The dependencies between the setups name a setup which does not exist, or form a cycle.
+    Dependencies(String),
+    ///The setup failed on one or more machines, each of which is listed.
+    Setup(Vec<SetupFailure>),
+    ///The main script returned an error.
+    Script(BoxError),
+    ///Waiting for the spot requests or the instances took too long.
+    Timeout(Timeout),
+    ///A setup did not get enough machines.
+    Shortfall(Shortfall),
+    ///The burst ran past its maximum duration, and its instances were terminated.
+    Deadline(Duration),
+    ///Some resources could not be released after the burst, and may be left behind.
+    Teardown(Box<Error>),
+    ///The prices of the machines needed to estimate the cost of the burst are not known.
+    Prices(String),
+    ///The burst may cost more than its budget, so nothing was launched.
+    OverBudget {
+        ///what the burst may cost
+        estimate: Estimate,
+        ///the budget in USD
+        budget: f64,
+    },
+    ///The sweep does not fit the setups, such as varying the machines of a setup which does not
+    ///exist.
+    Sweep(String),
+    ///The state file of a cluster left running can not be used.
+    ClusterState {
+        ///the state file
+        path: PathBuf,
+        ///why it can not be used
+        reason: String,
+    },
+}
+
+///The failure of the setup of one machine.
+#[derive(Debug)]
+pub struct SetupFailure {
+    ///the name of the setup
+    pub name: String,
+    ///the public ip of the machine
+    pub public_ip: String,
+    ///what failed
+    pub error: Error,
+}
+
+impl Error {
+    ///Creates a [`Error::Provider`] error, for implementations of
+    ///[`Provider`](crate::provider::Provider).
+    pub fn provider(action: impl Into<String>, source: impl Into<BoxError>) -> Self {
+        Error::Provider {
+            action: action.into(),
+            source: source.into(),
+        }
+    }
+
+    pub(crate) fn io(action: impl Into<String>, source: std::io::Error) -> Self {
+        Error::Io {
+            action: action.into(),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Provider { action, source } => write!(f, "failed to {}: {}", action, source),
+            Error::Connect { address, source } => {
+                write!(f, "failed to connect to {}: {}", address, source)
+            }
+            Error::HostKey { address, source } => write!(
+                f,
+                "the host key of {} could not be verified: {}",
+                address, source
+            ),
+            Error::Auth {
+                address,
+                username,
+                source,
+            } => write!(
+                f,
+                "failed to authenticate as {} on {}: {}",
+                username, address, source
+            ),
+            Error::Command { command, source } => {
+                write!(f, "failed to run `{}`: {}", command, source)
+            }
+            Error::Exit { command, output } => {
+                match output.status {
+                    Some(status) => write!(f, "`{}` exited with status {}", command, status)?,
+                    None => write!(f, "`{}` was killed by a signal", command)?,
+                }
+                match output.stderr.trim() {
+                    "" => Ok(()),
+                    stderr => write!(f, ": {}", stderr),
+                }
+            }
+            Error::Transfer { from, to, source } => {
+                write!(f, "failed to copy {} to {}: {}", from, to, source)
+            }
+            Error::Io { action, source } => write!(f, "failed to {}: {}", action, source),
+            Error::Closure(source) => write!(f, "{}", source),
+            Error::Dependencies(reason) => {
+                write!(f, "the setups can not be ordered: {}", reason)
+            }
+            Error::Setup(failures) => {
+                write!(f, "the setup failed on {} machine(s)", failures.len())?;
+                for failure in failures {
+                    write!(
+                        f,
+                        "; {} on {}: {}",
+                        failure.name, failure.public_ip, failure.error
+                    )?;
+                }
+                Ok(())
+            }
+            Error::Script(source) => write!(f, "the main script failed: {}", source),
+            Error::Timeout(timeout) => write!(f, "{}", timeout),
+            Error::Shortfall(shortfall) => write!(f, "{}", shortfall),
+            Error::Deadline(duration) => write!(
+                f,
+                "the burst exceeded its maximum duration of {:?}",
+                duration
+            ),
+            Error::Teardown(error) => write!(f, "the teardown failed: {}", error),
+            Error::Sweep(reason) => write!(f, "the sweep can not be run: {}", reason),
+            Error::Prices(reason) => {
+                write!(f, "the cost of the burst can not be estimated: {}", reason)
+            }
+            Error::OverBudget { estimate, budget } => write!(
+                f,
+                "the burst may cost up to ${:.2}, more than its budget of ${:.2}",
+                estimate.max_duration, budget
+            ),
+            Error::ClusterState { path, reason } => write!(
+                f,
+                "the cluster state in {} can not be used: {}",
+                path.display(),
+                reason
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Provider { source, .. }
+            | Error::Connect { source, .. }
+            | Error::HostKey { source, .. }
+            | Error::Auth { source, .. }
+            | Error::Command { source, .. }
+            | Error::Transfer { source, .. }
+            | Error::Closure(source)
+            | Error::Script(source) => Some(source.as_ref()),
+            Error::Io { source, .. } => Some(source),
+            Error::Timeout(timeout) => Some(timeout),
+            Error::Shortfall(shortfall) => Some(shortfall),
+            Error::Teardown(error) => Some(error.as_ref()),
+            Error::Exit { .. }
+            | Error::Dependencies(_)
+            | Error::Setup(_)
+            | Error::Deadline(_)
+            | Error::Sweep(_)
+            | Error::Prices(_)
+            | Error::OverBudget { .. }
+            | Error::ClusterState { .. } => None,
+        }
+    }
+}
+
+impl From<Timeout> for Error {
+    fn from(timeout: Timeout) -> Self {
+        Error::Timeout(timeout)
+    }
+}
+
+impl From<Shortfall> for Error {
+    fn from(shortfall: Shortfall) -> Self {
+        Error::Shortfall(shortfall)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn setup_failures_are_all_reported() {
+        let error = Error::Setup(vec![
+            SetupFailure {
+                name: "server".to_string(),
+                public_ip: "203.0.113.1".to_string(),
+                error: Error::Closure("yum install failed".into()),
+            },
+            SetupFailure {
+                name: "client".to_string(),
+                public_ip: "203.0.113.2".to_string(),
+                error: Error::Connect {
+                    address: "203.0.113.2:22".to_string(),
+                    source: "connection refused".into(),
+                },
+            },
+        ]);
+
+        assert_eq!(
+            error.to_string(),
+            "the setup failed on 2 machine(s); server on 203.0.113.1: yum install failed; \
+             client on 203.0.113.2: failed to connect to 203.0.113.2:22: connection refused"
+        );
+    }
+}
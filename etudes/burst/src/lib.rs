@@ -7,8 +7,7 @@
 //!purposes.
 //!
 //!```rust, no_run
-//! # use burst::{BurstBuilder, Machine, MachineSetup};
-//! # use std::collections::HashMap;
+//! # use burst::{BurstBuilder, MachineSetup};
 //! # use tokio::runtime::Runtime;
 //!
 //! # let mut builder = BurstBuilder::default();
@@ -16,7 +15,7 @@
 //! # rt.block_on(
 //! async {
 //!        builder
-//!            .run(|vms: HashMap<String, Vec<Machine>>| {
+//!            .run(|vms| {
 //!                println!("server private ip: {}", vms["server"][0].private_ip);
 //!                println!("client private ip: {}", vms["client"][0].private_ip);
 //!
@@ -28,25 +27,57 @@
 //! # );
 #![deny(missing_docs)]
 
-use failure::ResultExt;
+use provider::Provider;
 use rayon::prelude::*;
-use rusoto_ec2::Ec2;
 use slog::Discard;
 use slog::Logger;
 use std::collections::HashMap;
-use std::str::FromStr;
+use std::future::Future;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use teardown::Teardown;
 use tokio::time;
 #[macro_use]
 extern crate slog;
-use slog_term;
 
-mod ssh;
+mod cluster;
+mod cost;
+mod error;
+mod network;
+mod phases;
+pub mod provider;
+mod reaper;
+mod report;
+pub mod ssh;
+mod state;
+mod sweep;
+mod teardown;
+#[cfg(test)]
+mod testing;
+mod topology;
+mod wait;
+mod watchdog;
+
+pub use cluster::{Cluster, Fulfillment, Shortfall};
+pub use cost::{Estimate, PriceTable, SetupCost};
+pub use error::{BoxError, Error, SetupFailure};
+pub use network::{Ingress, Placement, Source};
+pub use provider::RetryPolicy;
+pub use reaper::{leftovers, reap};
+pub use report::{Metric, Report, SetupReport};
+pub use sweep::{Iteration, IterationResult, Sweep};
+pub use topology::{MachineInfo, Topology};
+pub use wait::{Backoff, Timeout, WaitPhase};
+
+pub use rusoto_core::Region;
+pub use rusoto_credential as credential;
 
-extern crate failure;
-extern crate failure_derive;
 extern crate rusoto;
 extern crate rusoto_core;
-extern crate rusoto_credential;
 extern crate rusoto_ec2;
 extern crate scopeguard;
 extern crate ssh2;
@@ -66,23 +97,76 @@ extern crate ssh2;
 ///     MachineSetup::new("t2.micro", "ami-083e865b97bdf1c1b", |ssh| {
 ///         let result = ssh.cmd("cat etc/hostname")?;
 ///         println!("ip addr: {}", result);
-
+///
 ///         Ok(())
 ///     }),
 /// );
 /// ```
 pub struct BurstBuilder {
     descriptors: std::collections::HashMap<String, (MachineSetup, i64)>,
-    max_duration_time: i64,
+    max_duration: Duration,
+    backoff: Backoff,
+    spot_request_timeout: Duration,
+    instance_timeout: Duration,
+    host_key_timeout: Duration,
+    host_key_policy: HostKeyPolicy,
+    retry_policy: RetryPolicy,
+    ingress: Vec<Ingress>,
+    vpc_cidr: Option<String>,
+    placement: Option<Placement>,
+    lifecycle: Lifecycle,
+    price_table: PriceTable,
+    budget: Option<f64>,
+    report_dir: Option<PathBuf>,
+    last_report: Option<Report>,
     logger: Logger,
+    region: Region,
+    credentials: Credentials,
+}
+
+//A run from its start to its teardown, or to its cluster being left running.
+struct Run<'a, P: Provider> {
+    teardown: Teardown<'a, P>,
+    deadline: Instant,
+    key_file: PathBuf,
+    known_hosts: PathBuf,
+    // holds the private key and the known hosts of a cluster which is not kept alive, and is
+    // removed along with them once the run is over
+    _run_dir: Option<tempfile::TempDir>,
+    state: state::ClusterState,
+    provisioned: bool,
+    recorder: report::Recorder,
+}
+
+//How `run` authenticates against EC2.
+enum Credentials {
+    Default,
+    Profile(String),
+    Custom(Arc<dyn Fn(Region) -> Result<provider::Ec2, Error> + Send + Sync>),
 }
 
 impl Default for BurstBuilder {
     fn default() -> Self {
         Self {
             descriptors: Default::default(),
-            max_duration_time: 60,
+            max_duration: Duration::from_secs(60 * 60),
+            backoff: Backoff::default(),
+            spot_request_timeout: Duration::from_secs(10 * 60),
+            instance_timeout: Duration::from_secs(10 * 60),
+            host_key_timeout: Duration::from_secs(10 * 60),
+            host_key_policy: HostKeyPolicy::default(),
+            retry_policy: RetryPolicy::default(),
+            ingress: Ingress::defaults(),
+            vpc_cidr: None,
+            placement: None,
+            lifecycle: Lifecycle::default(),
+            price_table: PriceTable::default(),
+            budget: None,
+            report_dir: None,
+            last_report: None,
             logger: Logger::root(Discard, o!()),
+            region: Region::UsEast1,
+            credentials: Credentials::Default,
         }
     }
 }
@@ -95,9 +179,87 @@ impl Default for BurstBuilder {
 pub struct MachineSetup {
     instance_type: String,
     ami: String,
-    setup: Box<dyn Fn(&mut ssh::Session) -> Result<(), failure::Error> + Sync>,
+    purchase: PurchaseOption,
+    shortfall: ShortfallPolicy,
+    connect: ssh::ConnectOptions,
+    dependencies: Vec<String>,
+    setup: Box<SetupFn>,
+}
+
+///How the instances of a machine setup are bought.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PurchaseOption {
+    ///Spot instances, capped at the given hourly price in USD or at the on-demand price.
+    Spot {
+        ///the maximum hourly price
+        max_price: Option<f64>,
+    },
+    ///On-demand instances.
+    OnDemand,
+    ///Spot instances, with an on-demand instance launched for every spot request which the spot
+    ///market does not have capacity for.
+    SpotWithFallback {
+        ///the maximum hourly price
+        max_price: Option<f64>,
+    },
+}
+
+impl Default for PurchaseOption {
+    fn default() -> Self {
+        PurchaseOption::Spot { max_price: None }
+    }
+}
+
+///What `run` does when fewer machines of a setup could be launched than were requested.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShortfallPolicy {
+    ///Fail the run with a [`Shortfall`] error, after tearing everything down.
+    #[default]
+    Fail,
+    ///Request the missing machines again, up to the given number of times, and fail if some are
+    ///still missing.
+    Retry {
+        ///how many times the missing machines are requested again
+        rounds: usize,
+    },
+    ///Go on with the machines which were launched, as long as there are at least this many.
+    Minimum(i64),
+}
+
+///How `run` checks the ssh host keys of the machines it connects to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HostKeyPolicy {
+    ///Trust the key each machine presents on the first connection, recording it in a known_hosts
+    ///file which lives as long as the run, and refuse any other key afterwards.
+    #[default]
+    TrustOnFirstUse,
+    ///Only accept the keys each machine lists in its console output, waiting for them as the
+    ///[`WaitPhase::HostKeys`] phase. The provider must support
+    ///[`console_output`](provider::Provider::console_output).
+    ConsoleOutput,
+    ///Accept any host key.
+    AcceptAny,
+}
+
+///What `run` does with the cluster of the burst, see [`BurstBuilder::set_lifecycle`].
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Lifecycle {
+    ///Launch a cluster for the run and tear it down once the script is done.
+    #[default]
+    Ephemeral,
+    ///Launch a cluster and leave it running once the script is done, described in the given
+    ///state file. The private key and the known hosts are stored next to it, with the `pem` and
+    ///`known_hosts` extensions. The machines still shut themselves down once the maximum
+    ///duration has passed since the launch.
+    KeepAlive(PathBuf),
+    ///Run the script on the cluster described in the given state file, as left running by an
+    ///earlier run, and leave it running. Nothing is launched and the setups do not run again;
+    ///only the connect options of the setups are used.
+    Attach(PathBuf),
 }
 
+type SetupFn = dyn Fn(&mut ssh::Session, &Topology) -> Result<(), BoxError> + Sync;
+
 impl MachineSetup {
     ///Creates  new AWS spot instance machin setup template
     ///```rust
@@ -111,14 +273,98 @@ impl MachineSetup {
     ///```
     pub fn new<F>(instance_type: &str, ami: &str, setup: F) -> Self
     where
-        F: Fn(&mut ssh::Session) -> Result<(), failure::Error> + 'static + Sync,
+        F: Fn(&mut ssh::Session) -> Result<(), BoxError> + 'static + Sync,
+    {
+        Self::new_with_topology(instance_type, ami, move |ssh, _| setup(ssh))
+    }
+
+    ///Creates a machine setup whose setup closure also gets the [`Topology`] of the burst, such
+    ///as the addresses of the machines of the other setups
+    ///```rust
+    /// # use burst::MachineSetup;
+    /// MachineSetup::new_with_topology("t2.micro", "ami-083e865b97bdf1c1b", |ssh, topology| {
+    ///     let server = topology.private_ips("server")[0];
+    ///     ssh.cmd(&format!("echo 'server={}' > bench.conf", server))?;
+    ///     Ok(())
+    /// });
+    ///```
+    pub fn new_with_topology<F>(instance_type: &str, ami: &str, setup: F) -> Self
+    where
+        F: Fn(&mut ssh::Session, &Topology) -> Result<(), BoxError> + 'static + Sync,
     {
         Self {
             instance_type: instance_type.to_string(),
             ami: ami.to_string(),
+            purchase: PurchaseOption::default(),
+            shortfall: ShortfallPolicy::default(),
+            connect: ssh::ConnectOptions::default(),
+            dependencies: Vec::new(),
             setup: Box::new(setup),
         }
     }
+
+    ///Choose how the instances are bought, spot instances without a price cap by default
+    ///```rust
+    /// # use burst::{MachineSetup, PurchaseOption};
+    /// MachineSetup::new("c5.large", "ami-083e865b97bdf1c1b", |_| Ok(()))
+    ///     .with_purchase_option(PurchaseOption::SpotWithFallback {
+    ///         max_price: Some(0.05),
+    ///     });
+    ///```
+    pub fn with_purchase_option(mut self, purchase: PurchaseOption) -> Self {
+        self.purchase = purchase;
+        self
+    }
+
+    ///Choose what happens when fewer machines could be launched than requested, failing the run
+    ///by default
+    ///```rust
+    /// # use burst::{MachineSetup, ShortfallPolicy};
+    /// MachineSetup::new("c5.large", "ami-083e865b97bdf1c1b", |_| Ok(()))
+    ///     .with_shortfall_policy(ShortfallPolicy::Retry { rounds: 2 });
+    ///```
+    pub fn with_shortfall_policy(mut self, shortfall: ShortfallPolicy) -> Self {
+        self.shortfall = shortfall;
+        self
+    }
+
+    ///Choose how the machines are connected to over ssh, as `ec2-user` on port 22 by default
+    ///```rust
+    /// # use burst::{ssh::ConnectOptions, MachineSetup};
+    /// # use std::time::Duration;
+    /// MachineSetup::new("c5.large", "ami-0fc5d935ebf8bc3bc", |_| Ok(())).with_connect_options(
+    ///     ConnectOptions {
+    ///         username: "ubuntu".to_string(),
+    ///         retry_for: Duration::from_secs(300),
+    ///         ..Default::default()
+    ///     },
+    /// );
+    ///```
+    pub fn with_connect_options(mut self, connect: ssh::ConnectOptions) -> Self {
+        self.connect = connect;
+        self
+    }
+
+    ///Set up the machines only once every machine of the named setup is set up. Setups which do
+    ///not depend on each other are set up at the same time, and the machines they are set up
+    ///after can be found in the [`Topology`]
+    ///```rust
+    /// # use burst::MachineSetup;
+    /// MachineSetup::new_with_topology("t2.micro", "ami-083e865b97bdf1c1b", |ssh, topology| {
+    ///     let server = topology.private_ips("server")[0];
+    ///     ssh.cmd(&format!("curl --fail http://{}:8080/ready", server))?;
+    ///     Ok(())
+    /// })
+    /// .with_dependency("server");
+    ///```
+    pub fn with_dependency(mut self, name: &str) -> Self {
+        self.dependencies.push(name.to_string());
+        self
+    }
+
+    fn falls_back(&self) -> bool {
+        matches!(self.purchase, PurchaseOption::SpotWithFallback { .. })
+    }
 }
 
 impl BurstBuilder {
@@ -129,7 +375,184 @@ impl BurstBuilder {
 
     ///Set the max duration hour for spawning the aws ec2 spot instances
     pub fn set_max_duration_hour(&mut self, hour: u8) {
-        self.max_duration_time = hour as i64 * 60;
+        self.set_max_duration(Duration::from_secs(hour as u64 * 60 * 60));
+    }
+
+    ///Set the max duration of the burst, one hour by default. Once it has passed since `run`
+    ///started, the instances are terminated, which makes every following command of the setups
+    ///or of the main script fail, and `run` returns an error. Each instance also schedules its
+    ///own shutdown for the deadline, in case the controlling process dies.
+    pub fn set_max_duration(&mut self, duration: Duration) {
+        self.max_duration = duration;
+    }
+
+    ///Set how long to sleep between polls while waiting for spot requests and instances
+    pub fn set_backoff(&mut self, backoff: Backoff) {
+        self.backoff = backoff;
+    }
+
+    ///Set how long a wait phase may take before `run` fails with a [`Timeout`], ten minutes by
+    ///default for each phase
+    ///```rust
+    /// # use burst::{BurstBuilder, WaitPhase};
+    /// # use std::time::Duration;
+    /// let mut builder = BurstBuilder::default();
+    /// builder.set_wait_timeout(WaitPhase::SpotRequests, Duration::from_secs(120));
+    ///```
+    pub fn set_wait_timeout(&mut self, phase: WaitPhase, timeout: Duration) {
+        match phase {
+            WaitPhase::SpotRequests => self.spot_request_timeout = timeout,
+            WaitPhase::Instances => self.instance_timeout = timeout,
+            WaitPhase::HostKeys => self.host_key_timeout = timeout,
+        }
+    }
+
+    ///Set how the ssh host keys of the machines are checked, trusting them on first use by
+    ///default
+    ///```rust
+    /// # use burst::{BurstBuilder, HostKeyPolicy};
+    /// let mut builder = BurstBuilder::default();
+    /// builder.set_host_key_policy(HostKeyPolicy::ConsoleOutput);
+    ///```
+    pub fn set_host_key_policy(&mut self, policy: HostKeyPolicy) {
+        self.host_key_policy = policy;
+    }
+
+    ///Set how the calls to the cloud provider which fail with a transient error, such as
+    ///throttling or a resource which was just created and is not visible yet, are retried
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = policy;
+    }
+
+    ///Set the inbound rules of the security group of the machines, replacing the default ones
+    ///which let in ssh from anywhere and any tcp traffic between the machines. The rules must let
+    ///in ssh from this machine, or `run` can not reach the machines.
+    ///```rust
+    /// # use burst::{BurstBuilder, Ingress, Source};
+    /// let mut builder = BurstBuilder::default();
+    /// builder.set_ingress_rules(vec![
+    ///     Ingress::tcp(22..=22, Source::ThisMachine),
+    ///     Ingress::tcp(0..=65535, Source::Cluster),
+    ///     Ingress::udp(9000..=9100, Source::Cluster),
+    /// ]);
+    ///```
+    pub fn set_ingress_rules(&mut self, rules: Vec<Ingress>) {
+        self.ingress = rules;
+    }
+
+    ///Add an inbound rule to the security group of the machines.
+    pub fn add_ingress_rule(&mut self, rule: Ingress) {
+        self.ingress.push(rule);
+    }
+
+    ///Launch the machines in a VPC of their own with the given address range, with a single
+    ///public subnet spanning it, instead of the default VPC. The VPC is deleted with the rest of
+    ///the run.
+    ///```rust
+    /// # use burst::{BurstBuilder, Placement};
+    /// let mut builder = BurstBuilder::default();
+    /// builder.use_dedicated_vpc("10.0.0.0/16");
+    /// builder.use_placement_group(Placement::Cluster);
+    ///```
+    pub fn use_dedicated_vpc(&mut self, cidr: &str) {
+        self.vpc_cidr = Some(cidr.to_string());
+    }
+
+    ///Launch the machines in a placement group created for the run with the given strategy.
+    pub fn use_placement_group(&mut self, placement: Placement) {
+        self.placement = Some(placement);
+    }
+
+    ///Set what `run` does with the cluster, which is launched for the run and torn down
+    ///afterwards by default. Keeping a cluster alive and attaching to it saves the minutes of the
+    ///launch and the setups while tuning a benchmark; [`destroy`](Self::destroy) releases it.
+    ///```rust
+    /// # use burst::{BurstBuilder, Lifecycle};
+    /// let mut builder = BurstBuilder::default();
+    /// let state = std::path::Path::new("bench.json");
+    /// if state.exists() {
+    ///     builder.set_lifecycle(Lifecycle::Attach(state.into()));
+    /// } else {
+    ///     builder.set_lifecycle(Lifecycle::KeepAlive(state.into()));
+    /// }
+    ///```
+    pub fn set_lifecycle(&mut self, lifecycle: Lifecycle) {
+        self.lifecycle = lifecycle;
+    }
+
+    ///Set where the prices of the instance types come from, the spot price history of the
+    ///provider by default.
+    pub fn set_price_table(&mut self, table: PriceTable) {
+        self.price_table = table;
+    }
+
+    ///Refuse to launch the machines when they may cost more than the given USD over the maximum
+    ///duration, as estimated from the price table before anything is created.
+    ///```rust
+    /// # use burst::{BurstBuilder, PriceTable};
+    /// let mut builder = BurstBuilder::default();
+    /// builder.set_price_table(PriceTable::File("prices.json".into()));
+    /// builder.set_budget(20.0);
+    ///```
+    pub fn set_budget(&mut self, budget: f64) {
+        self.budget = Some(budget);
+    }
+
+    ///Write the report of every run to `burst_<run id>.json` and `burst_<run id>.csv` in the
+    ///given directory, which is created if needed. Runs whose main script did not start have no
+    ///report.
+    pub fn set_report_dir(&mut self, dir: impl AsRef<Path>) {
+        self.report_dir = Some(dir.as_ref().to_path_buf());
+    }
+
+    ///The report of the last run whose main script started, with the metrics it recorded
+    ///through [`Cluster::record`] and [`Machine::record`].
+    pub fn last_report(&self) -> Option<&Report> {
+        self.last_report.as_ref()
+    }
+
+    ///Set the AWS region the machines are launched in, `us-east-1` by default
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    ///Send the EC2 requests to a custom endpoint, such as a local EC2-compatible emulator, which
+    ///requests are signed for as the given region name
+    ///```rust
+    /// # use burst::BurstBuilder;
+    /// let mut builder = BurstBuilder::default();
+    /// builder.set_endpoint("us-east-1", "http://localhost:5000");
+    ///```
+    pub fn set_endpoint(&mut self, region_name: &str, endpoint: &str) {
+        self.region = Region::Custom {
+            name: region_name.to_string(),
+            endpoint: endpoint.to_string(),
+        };
+    }
+
+    ///Authenticate with the credentials of a named profile from the shared credentials file
+    pub fn use_profile(&mut self, profile: &str) {
+        self.credentials = Credentials::Profile(profile.to_string());
+    }
+
+    ///Authenticate with a custom credentials provider, such as a provider chain or static keys
+    ///```rust
+    /// # use burst::{credential::StaticProvider, BurstBuilder};
+    /// let mut builder = BurstBuilder::default();
+    /// builder.use_credentials(StaticProvider::new_minimal(
+    ///     "access-key".to_string(),
+    ///     "secret-key".to_string(),
+    /// ));
+    ///```
+    pub fn use_credentials<P>(&mut self, credentials: P)
+    where
+        P: credential::ProvideAwsCredentials + Send + Sync + 'static,
+    {
+        // the client is only built by `run`, which reports the failure to build it
+        let credentials = Arc::new(credentials);
+        self.credentials = Credentials::Custom(Arc::new(move |region| {
+            provider::Ec2::with_credentials(region, credentials.clone())
+        }));
     }
 
     ///Assign a custom logger to the burst builder
@@ -148,434 +571,1824 @@ impl BurstBuilder {
         self.logger = Logger::root(drain, o!());
     }
 
-    fn rand_string(len: usize) -> String {
-        use rand::{Rng, distr::Alphabetic};
-
-        let mut rng = rand::rng();
-        (&mut rng)
-            .sample_iter(Alphabetic)
-            .take(len)
-            .map(char::from)
-            .collect()
+    ///Run the main burst routine on AWS EC2, and return erros in case of any error.
+    pub async fn run<F>(&mut self, script: F) -> Result<(), Error>
+    where
+        F: FnMut(Cluster) -> Result<(), BoxError>,
+    {
+        let ec2 = self.ec2()?;
+        self.run_with(&ec2, script).await
     }
 
-    ///Run the main burst routine, and return erros in case of any error.
-    pub async fn run<F>(&mut self, mut script: F) -> Result<(), failure::Error>
+    ///Run the main burst routine on AWS EC2 with an async script, which can drive the machines
+    ///concurrently with [`Machine::run_async`].
+    ///
+    ///```rust, no_run
+    /// # use burst::BurstBuilder;
+    /// # use tokio::runtime::Runtime;
+    /// # let mut builder = BurstBuilder::default();
+    /// # let rt = Runtime::new().unwrap();
+    /// # rt.block_on(async {
+    /// builder
+    ///     .run_async(|vms| async move {
+    ///         let server = &vms["server"][0];
+    ///         server.run_async("./server --daemon").await?;
+    ///         let client = format!("./client {}", server.private_ip);
+    ///         let (a, b) = tokio::join!(
+    ///             vms["client"][0].run_async(&client),
+    ///             vms["client"][1].run_async(&client),
+    ///         );
+    ///         println!("{} {}", a?, b?);
+    ///         Ok(())
+    ///     })
+    ///     .await
+    ///     .unwrap();
+    /// # });
+    ///```
+    pub async fn run_async<F, Fut>(&mut self, script: F) -> Result<(), Error>
     where
-        F: FnMut(std::collections::HashMap<String, Vec<Machine>>) -> Result<(), failure::Error>,
+        F: FnOnce(Cluster) -> Fut,
+        Fut: Future<Output = Result<(), BoxError>>,
     {
-        debug!(self.logger, "connecting to ec2");
-        //use scopeguard::ScopeGuard;
-        //Creates a client backed by the default tokio event loop.
-        //The client will use the default credentials provider and tls client.
-        let ec2 = rusoto_ec2::Ec2Client::new(rusoto_core::Region::UsEast1);
-        debug!(self.logger, "connected to ec2");
+        let ec2 = self.ec2()?;
+        self.run_with_async(&ec2, script).await
+    }
 
-        //Create a security group
-        let security_group_name = format!("burst_sg_{}", Self::rand_string(10));
-        trace!(self.logger,"Creating a secutiry group";"group_name"=>&security_group_name);
-        let sg_req = rusoto_ec2::CreateSecurityGroupRequest {
-            group_name: security_group_name,
-            description: "Security group for burst".to_string(),
-            ..Default::default()
+    fn ec2(&self) -> Result<provider::Ec2, Error> {
+        debug!(self.logger, "connecting to ec2"; "region"=>self.region.name());
+        let region = self.region.clone();
+        let ec2 = match &self.credentials {
+            Credentials::Default => provider::Ec2::new(region),
+            Credentials::Profile(profile) => provider::Ec2::with_profile(region, profile)?,
+            Credentials::Custom(ec2) => ec2(region)?,
         };
-        let group_id = ec2
-            .create_security_group(sg_req)
-            .await
-            .context("failed to create security group")?
-            .group_id
-            .expect("aws creates security group always with an id");
+        debug!(self.logger, "connected to ec2");
+        Ok(ec2)
+    }
 
-        trace!(self.logger, "security group created";"group_id"=>&group_id.clone());
+    ///Run the main burst routine against the given provider, and return erros in case of any
+    ///error.
+    ///
+    ///```rust
+    /// # use burst::{provider::Fake, BurstBuilder};
+    /// # use tokio::runtime::Runtime;
+    /// let mut builder = BurstBuilder::default();
+    /// let fake = Fake::new();
+    /// let rt = Runtime::new().unwrap();
+    /// rt.block_on(builder.run_with(&fake, |vms| {
+    ///     assert!(vms.is_empty());
+    ///     Ok(())
+    /// }))
+    /// .unwrap();
+    ///```
+    pub async fn run_with<P, F>(&mut self, provider: &P, mut script: F) -> Result<(), Error>
+    where
+        P: Provider,
+        F: FnMut(Cluster) -> Result<(), BoxError>,
+    {
+        let provider =
+            &provider::Retrying::new(provider, self.retry_policy.clone(), self.logger.clone());
+        // everything created from here on is owned by the teardown guard of the run, which
+        // releases it even if the burst fails or panics half way through
+        let mut run = self.start(provider)?;
+        let result = match self.provision(provider, &mut run, &self.counts()).await {
+            Ok(cluster) => {
+                let instance_ids = cluster.instance_ids();
+                let recorder = &run.recorder;
+                self.within_deadline(watchdog::run_until(
+                    provider,
+                    &self.logger,
+                    &instance_ids,
+                    run.deadline,
+                    || {
+                        let start = self.script_started();
+                        self.script_finished(recorder, start, script(cluster))
+                    },
+                ))
+            }
+            Err(e) => Err(e),
+        };
+        let report = self.report(&run, &result);
+        let finished = self.finish(run, result).await;
+        finished.and(self.keep_report(report))
+    }
 
-        trace!(self.logger, "adding ip permissions to the security group");
-        let ssh_permission = rusoto_ec2::IpPermission {
-            from_port: Some(22),
-            to_port: Some(22),
-            ip_protocol: Some("tcp".to_string()),
-            ip_ranges: Some(vec![rusoto_ec2::IpRange {
-                cidr_ip: Some("0.0.0.0/0".to_string()),
-                ..Default::default()
-            }]),
-            ..Default::default()
+    ///Run the main burst routine against the given provider with an async script, and return
+    ///erros in case of any error.
+    pub async fn run_with_async<P, F, Fut>(&mut self, provider: &P, script: F) -> Result<(), Error>
+    where
+        P: Provider,
+        F: FnOnce(Cluster) -> Fut,
+        Fut: Future<Output = Result<(), BoxError>>,
+    {
+        let provider =
+            &provider::Retrying::new(provider, self.retry_policy.clone(), self.logger.clone());
+        let mut run = self.start(provider)?;
+        let result = match self.provision(provider, &mut run, &self.counts()).await {
+            Ok(cluster) => {
+                let instance_ids = cluster.instance_ids();
+                let recorder = &run.recorder;
+                self.within_deadline(
+                    watchdog::run_until_async(
+                        provider,
+                        &self.logger,
+                        &instance_ids,
+                        run.deadline,
+                        self.max_duration,
+                        async {
+                            let start = self.script_started();
+                            self.script_finished(recorder, start, script(cluster).await)
+                        },
+                    )
+                    .await,
+                )
+            }
+            Err(e) => Err(e),
         };
+        let report = self.report(&run, &result);
+        let finished = self.finish(run, result).await;
+        finished.and(self.keep_report(report))
+    }
+
+    ///Run the main script once for every iteration of the sweep on AWS EC2, on a cluster launched
+    ///once for the largest machine counts of the sweep, and return how each iteration went.
+    ///
+    ///The script is handed the first machines of each setup the iteration varies, and all the
+    ///machines of the others. An iteration which fails or panics does not stop the sweep; the
+    ///sweep fails as a whole only if the cluster can not be launched or torn down, or runs past
+    ///the maximum duration. Every iteration has a report of its own, written next to the others if
+    ///the builder has a report directory.
+    ///```rust, no_run
+    /// # use burst::{BurstBuilder, Sweep};
+    /// # async fn f(mut builder: BurstBuilder) -> Result<(), burst::Error> {
+    /// let sweep = Sweep::new()
+    ///     .with_machines("client", [1, 2, 4, 8])
+    ///     .with_parameter("payload", ["1k", "1m"]);
+    /// let results = builder
+    ///     .sweep(&sweep, |iteration, vms| {
+    ///         let payload = iteration.parameter("payload").unwrap_or("1k");
+    ///         for vm in &vms["client"] {
+    ///             let output = vm.exec(&format!("./client --payload {}", payload))?;
+    ///             vm.record("seconds", output.duration.as_secs_f64());
+    ///         }
+    ///         Ok(())
+    ///     })
+    ///     .await?;
+    /// for result in results.iter().filter(|result| result.result.is_err()) {
+    ///     println!("{:?} failed", result.iteration);
+    /// }
+    /// # Ok(())
+    /// # }
+    ///```
+    pub async fn sweep<F>(
+        &mut self,
+        sweep: &Sweep,
+        script: F,
+    ) -> Result<Vec<IterationResult>, Error>
+    where
+        F: FnMut(&Iteration, &Cluster) -> Result<(), BoxError>,
+    {
+        let ec2 = self.ec2()?;
+        self.sweep_with(&ec2, sweep, script).await
+    }
+
+    ///Run the main script once for every iteration of the sweep against the given provider.
+    pub async fn sweep_with<P, F>(
+        &mut self,
+        provider: &P,
+        sweep: &Sweep,
+        mut script: F,
+    ) -> Result<Vec<IterationResult>, Error>
+    where
+        P: Provider,
+        F: FnMut(&Iteration, &Cluster) -> Result<(), BoxError>,
+    {
+        // the cluster attached to is as large as it was launched
+        let mut counts = self.counts();
+        if !matches!(self.lifecycle, Lifecycle::Attach(_)) {
+            for (name, largest) in sweep.largest_counts() {
+                let number = counts.get_mut(name).ok_or_else(|| {
+                    Error::Sweep(format!(
+                        "it varies the machines of {} which is not a setup",
+                        name
+                    ))
+                })?;
+                *number = largest as i64;
+            }
+        }
 
-        let tcp_cross_permission = rusoto_ec2::IpPermission {
-            from_port: Some(0),
-            to_port: Some(65535),
-            ip_protocol: Some("tcp".to_string()),
-            ip_ranges: Some(vec![rusoto_ec2::IpRange {
-                cidr_ip: Some("172.31.0.0/16".to_string()),
-                ..Default::default()
-            }]),
-            ..Default::default()
+        let provider =
+            &provider::Retrying::new(provider, self.retry_policy.clone(), self.logger.clone());
+        let mut run = self.start(provider)?;
+        let mut results = Vec::new();
+        let result = match self.provision(provider, &mut run, &counts).await {
+            Ok(mut cluster) => {
+                let instance_ids = cluster.instance_ids();
+                let run = &run;
+                self.within_deadline(watchdog::run_until(
+                    provider,
+                    &self.logger,
+                    &instance_ids,
+                    run.deadline,
+                    || {
+                        for iteration in sweep.iterations() {
+                            let result = self.iterate(run, &mut cluster, iteration, &mut script);
+                            results.push(result);
+                        }
+                        Ok(())
+                    },
+                ))
+            }
+            Err(e) => Err(e),
         };
+        let finished = self.finish(run, result).await;
+        let mut written = Ok(());
+        for result in &results {
+            written = written.and(self.keep_report(result.report.clone()));
+        }
+        finished.and(written).map(|()| results)
+    }
 
-        let sg_inbound_rule_req = rusoto_ec2::AuthorizeSecurityGroupIngressRequest {
-            group_id: Some(group_id.clone()),
-            ip_permissions: Some(vec![ssh_permission, tcp_cross_permission]),
-            ..Default::default()
+    ///Run an iteration of a sweep on its machines of the cluster.
+    fn iterate<P, F>(
+        &self,
+        run: &Run<'_, P>,
+        cluster: &mut Cluster,
+        iteration: Iteration,
+        script: &mut F,
+    ) -> IterationResult
+    where
+        P: Provider,
+        F: FnMut(&Iteration, &Cluster) -> Result<(), BoxError>,
+    {
+        info!(self.logger, "running an iteration of the sweep"; "iteration"=>iteration.index, "machines"=>?iteration.machines, "parameters"=>?iteration.parameters);
+        run.recorder.clear();
+        let result = match cluster.set_aside(&iteration.machines) {
+            Ok(aside) => {
+                let start = self.script_started();
+                // a panic fails its own iteration only, as an error would
+                let result =
+                    std::panic::catch_unwind(AssertUnwindSafe(|| script(&iteration, cluster)))
+                        .unwrap_or_else(|panic| Err(panicked(panic).into()));
+                let result = self.script_finished(&run.recorder, start, result);
+                cluster.put_back(aside);
+                result
+            }
+            Err(shortfall) => Err(shortfall.into()),
         };
+        if let Err(e) = &result {
+            error!(self.logger, "the iteration failed"; "iteration"=>iteration.index, "error"=>%e);
+        }
+
+        let report = self.report(run, &result).map(|mut report| {
+            report.iteration = Some(iteration.index);
+            report.parameters = iteration.parameters.clone();
+            for (name, count) in &iteration.machines {
+                if let Some(setup) = report.setups.get_mut(name) {
+                    setup.machines = *count;
+                }
+            }
+            report
+        });
+        IterationResult {
+            iteration,
+            result,
+            report,
+        }
+    }
+
+    ///Estimate what the machines cost on AWS EC2, per hour and over the maximum duration.
+    pub async fn estimate(&self) -> Result<Estimate, Error> {
+        let ec2 = self.ec2()?;
+        self.estimate_with(&ec2).await
+    }
+
+    ///Estimate what the machines cost with the prices of the table, asking the given provider
+    ///for the spot prices if the table says so.
+    ///```rust
+    /// # use burst::{provider::Fake, BurstBuilder, MachineSetup};
+    /// # use tokio::runtime::Runtime;
+    /// let mut builder = BurstBuilder::default();
+    /// builder.add_setup(
+    ///     "client".to_string(),
+    ///     4,
+    ///     MachineSetup::new("c5.large", "ami-fake", |_| Ok(())),
+    /// );
+    /// let fake = Fake::new().with_spot_price("c5.large", 0.05);
+    /// let rt = Runtime::new().unwrap();
+    /// let estimate = rt.block_on(builder.estimate_with(&fake)).unwrap();
+    /// assert!((estimate.hourly - 0.2).abs() < 1e-9);
+    ///```
+    pub async fn estimate_with<P: Provider>(&self, provider: &P) -> Result<Estimate, Error> {
+        self.estimate_for(provider, &self.counts()).await
+    }
+
+    async fn estimate_for<P: Provider>(
+        &self,
+        provider: &P,
+        counts: &HashMap<String, i64>,
+    ) -> Result<Estimate, Error> {
+        let setups = self
+            .descriptors
+            .iter()
+            .map(|(name, (setup, _))| (name, setup, counts[name]));
+        cost::estimate(provider, &self.price_table, setups, self.max_duration).await
+    }
+
+    //The number of machines of each setup, as passed to `add_setup`.
+    fn counts(&self) -> HashMap<String, i64> {
+        self.descriptors
+            .iter()
+            .map(|(name, (_, number))| (name.clone(), *number))
+            .collect()
+    }
+
+    ///Destroy the cluster described by the given state file, as left running by a run with
+    ///[`Lifecycle::KeepAlive`], on AWS EC2. The state file and the files next to it are removed
+    ///once everything is released.
+    pub async fn destroy(&self, state: impl AsRef<Path>) -> Result<(), Error> {
+        let ec2 = self.ec2()?;
+        self.destroy_with(&ec2, state).await
+    }
 
-        let _ = ec2
-            .authorize_security_group_ingress(sg_inbound_rule_req)
+    ///Destroy the cluster described by the given state file against the given provider.
+    pub async fn destroy_with<P: Provider>(
+        &self,
+        provider: &P,
+        state: impl AsRef<Path>,
+    ) -> Result<(), Error> {
+        let path = state.as_ref();
+        let state = state::ClusterState::load(path)?;
+        info!(self.logger, "destroying a cluster left running"; "run_id"=>&state.run_id, "state"=>%path.display());
+        let provider =
+            &provider::Retrying::new(provider, self.retry_policy.clone(), self.logger.clone());
+        let mut teardown = Teardown::new(provider, self.logger.clone());
+        teardown.adopt(state.resources);
+        teardown
+            .release()
             .await
-            .context("Failed to setup security group permissions")?;
+            .map_err(|e| Error::Teardown(Box::new(e)))?;
+        state::remove(path)
+    }
 
-        //Create key pairs for ssh
-        let key_name = format!("burst_{}", Self::rand_string(6));
-        trace!(self.logger,"creating a key-pair";"key_name"=>&key_name);
-        let key_pair_req = rusoto_ec2::CreateKeyPairRequest {
-            key_name: key_name.clone(),
-            ..Default::default()
+    ///Start a run: load the cluster to attach to, or decide where the private key and the known
+    ///hosts of the cluster to launch go.
+    fn start<'a, P: Provider>(&self, provider: &'a P) -> Result<Run<'a, P>, Error> {
+        let teardown = Teardown::new(provider, self.logger.clone());
+        let new_state = |kept_alive| state::ClusterState::new(self.max_duration, kept_alive);
+        match &self.lifecycle {
+            Lifecycle::Ephemeral => {
+                let run_dir = tempfile::Builder::new()
+                    .prefix("burst_")
+                    .tempdir()
+                    .map_err(|e| Error::io("create a temp directory", e))?;
+                Ok(Run {
+                    teardown,
+                    deadline: Instant::now() + self.max_duration,
+                    key_file: run_dir.path().join("id_rsa"),
+                    known_hosts: run_dir.path().join("known_hosts"),
+                    _run_dir: Some(run_dir),
+                    state: new_state(false),
+                    provisioned: false,
+                    recorder: report::Recorder::default(),
+                })
+            }
+            Lifecycle::KeepAlive(path) => {
+                if path.exists() {
+                    return Err(Error::ClusterState {
+                        path: path.clone(),
+                        reason: "the file already exists, attach to the cluster it describes or \
+                                 destroy it first"
+                            .to_string(),
+                    });
+                }
+                Ok(Run {
+                    teardown,
+                    deadline: Instant::now() + self.max_duration,
+                    key_file: state::key_file(path),
+                    known_hosts: state::known_hosts(path),
+                    _run_dir: None,
+                    state: new_state(true),
+                    provisioned: false,
+                    recorder: report::Recorder::default(),
+                })
+            }
+            Lifecycle::Attach(path) => {
+                let state = state::ClusterState::load(path)?;
+                info!(self.logger, "attaching to a cluster left running"; "run_id"=>&state.run_id, "state"=>%path.display());
+                Ok(Run {
+                    teardown,
+                    deadline: state.deadline(path)?,
+                    key_file: state::key_file(path),
+                    known_hosts: state::known_hosts(path),
+                    _run_dir: None,
+                    state,
+                    provisioned: false,
+                    recorder: report::Recorder::default(),
+                })
+            }
+        }
+    }
+
+    ///Launch the machines and run the setups on them, or connect to the machines of the cluster
+    ///attached to, and return the cluster for the main script.
+    async fn provision<P: Provider>(
+        &self,
+        provider: &P,
+        run: &mut Run<'_, P>,
+        counts: &HashMap<String, i64>,
+    ) -> Result<Cluster, Error> {
+        let cluster = match self.lifecycle {
+            Lifecycle::Ephemeral | Lifecycle::KeepAlive(_) => {
+                self.check_budget(provider, counts).await?;
+                self.launch_and_set_up(provider, run, counts).await?
+            }
+            Lifecycle::Attach(_) => self.attach(provider, run).await?,
         };
+        run.provisioned = true;
+        Ok(cluster)
+    }
 
-        let key_pair = ec2
-            .create_key_pair(key_pair_req)
-            .await
-            .context("failed to generate the ec2 key-pairs")?;
+    ///Refuse to launch the machines if they may cost more than the budget.
+    async fn check_budget<P: Provider>(
+        &self,
+        provider: &P,
+        counts: &HashMap<String, i64>,
+    ) -> Result<(), Error> {
+        let Some(budget) = self.budget else {
+            return Ok(());
+        };
+        let estimate = self.estimate_for(provider, counts).await?;
+        info!(self.logger, "estimated the cost of the burst"; "hourly"=>estimate.hourly, "max_duration"=>estimate.max_duration, "budget"=>budget);
+        if estimate.max_duration > budget {
+            crit!(self.logger, "the burst may cost more than its budget"; "max_duration"=>estimate.max_duration, "budget"=>budget);
+            return Err(Error::OverBudget { estimate, budget });
+        }
+        Ok(())
+    }
 
-        trace!(self.logger,"key-pair generated"; "fingerprint"=>key_pair.key_fingerprint);
+    ///Tear the cluster down once the script is done, or leave it running as the lifecycle says.
+    async fn finish<P: Provider>(
+        &self,
+        mut run: Run<'_, P>,
+        result: Result<(), Error>,
+    ) -> Result<(), Error> {
+        let (path, attached) = match &self.lifecycle {
+            Lifecycle::Ephemeral => return self.tear_down(run.teardown, result).await,
+            Lifecycle::KeepAlive(path) => (path, false),
+            Lifecycle::Attach(path) => (path, true),
+        };
+        run.teardown.adopt(std::mem::take(&mut run.state.resources));
 
-        let key_pair_file =
-            tempfile::NamedTempFile::new().context("failed to create a temp file")?;
-        std::fs::write(
-            key_pair_file.path(),
-            key_pair
-                .key_material
-                .expect("the aws key-pair creation was successfull, so this should have value."),
-        )
-        .context(format!(
-            "failed to store the key-pair private key file : {}",
-            key_pair_file.path().to_str().unwrap()
-        ))?;
+        // past the deadline the watchdog terminated the instances, so the rest goes as well
+        let expired = matches!(result, Err(Error::Deadline(_)));
+        if expired || !(run.provisioned || attached) {
+            let removed = state::remove(path);
+            return self.tear_down(run.teardown, result).await.and(removed);
+        }
 
-        trace!(self.logger,"key pair private key stored into disk";"path"=>?key_pair_file.path());
-        //prefixing ? prints the
-        //display version of the
-        //value
+        run.state.resources = run.teardown.keep();
+        if run.provisioned {
+            if let Err(e) = run.state.save(path) {
+                crit!(self.logger, "the state of the cluster could not be stored, tearing it down"; "error"=>%e);
+                run.teardown.adopt(std::mem::take(&mut run.state.resources));
+                let removed = state::remove(path);
+                return self
+                    .tear_down(run.teardown, result.and(Err(e)))
+                    .await
+                    .and(removed);
+            }
+            info!(self.logger, "the cluster is left running"; "state"=>%path.display(), "run_id"=>&run.state.run_id);
+        }
+        result
+    }
 
-        // 1. issue spot requests
-        let mut id_to_name = HashMap::new();
-        let mut spot_instance_request_ids = Vec::new();
-        debug!(self.logger, "issuing the spot requests");
-        for (name, (setup, number)) in &self.descriptors {
-            let launch = rusoto_ec2::RequestSpotLaunchSpecification {
-                image_id: Some(setup.ami.clone()),
-                instance_type: Some(setup.instance_type.clone()),
-                security_group_ids: Some(vec![group_id.clone()]),
-                key_name: Some(key_name.clone()),
-                ..Default::default()
-            };
+    fn script_started(&self) -> time::Instant {
+        info!(self.logger, "running the burst");
+        time::Instant::now()
+    }
 
-            let req = rusoto_ec2::RequestSpotInstancesRequest {
-                launch_specification: Some(launch),
-                //block_duration_minutes: Some(self.max_duration_time),
-                instance_count: Some(*number),
-                //instance_interruption_behavior: Some("stop".to_string()),
-                ..Default::default()
-            };
-            trace!(self.logger, "issuing spot request for {}",name; "number"=>number);
-            let result = ec2
-                .request_spot_instances(req.clone())
-                .await
-                .map_err(failure::Error::from)
-                .map_err(|e| e.context(format!("failed to request spot instances for {}", name)))?;
+    fn script_finished(
+        &self,
+        recorder: &report::Recorder,
+        start: time::Instant,
+        result: Result<(), BoxError>,
+    ) -> Result<(), Error> {
+        let took = start.elapsed();
+        recorder.script_ran(SystemTime::now() - took, took);
+        result.map_err(|e| {
+            crit!(self.logger,"Error happend during runing the main procedure";"error"=>%e);
+            Error::Script(e)
+        })?;
+        info!(self.logger,"the burst run it finished";"took"=>?took);
+        Ok(())
+    }
 
-            let requests = result.spot_instance_requests.expect(
-                "request spot instances should always return one or more spot instance requests",
-            );
-            spot_instance_request_ids.extend(
-                requests
-                    .into_iter()
-                    .filter_map(|it| it.spot_instance_request_id)
-                    .map(|it| {
-                        trace!(self.logger,"spot request issued for {}",name; "spot_instance_request_id"=>it.clone());
-                        id_to_name.insert(it.clone(), name.clone());
-                        it
-                    }),
-            );
+    ///The report of the run, `None` if its main script did not start.
+    fn report<P: Provider>(&self, run: &Run<'_, P>, result: &Result<(), Error>) -> Option<Report> {
+        let setups = run
+            .state
+            .machines
+            .iter()
+            .map(|(name, group)| {
+                let setup = self.descriptors.get(name).map(|(setup, _)| setup);
+                let instance_type = match (setup, group.first()) {
+                    (Some(setup), _) => setup.instance_type.clone(),
+                    (None, Some(machine)) => machine.instance_type.clone(),
+                    (None, None) => String::new(),
+                };
+                let report = SetupReport {
+                    instance_type,
+                    ami: setup.map(|setup| setup.ami.clone()),
+                    machines: group.len(),
+                };
+                (name.clone(), report)
+            })
+            .collect();
+        let error = result.as_ref().err().map(|e| e.to_string());
+        run.recorder.report(&run.state.run_id, setups, error)
+    }
+
+    ///Write the report of the run to the report directory, if there is one, and keep it as the
+    ///last report.
+    fn keep_report(&mut self, report: Option<Report>) -> Result<(), Error> {
+        let Some(report) = report else {
+            return Ok(());
+        };
+        let written = match &self.report_dir {
+            Some(dir) => report.write_to(dir).inspect_err(|e| {
+                error!(self.logger, "the report of the run could not be written"; "error"=>%e);
+            }),
+            None => Ok(()),
+        };
+        self.last_report = Some(report);
+        written
+    }
+
+    fn within_deadline<T>(&self, (result, expired): (Result<T, Error>, bool)) -> Result<T, Error> {
+        if expired {
+            return Err(Error::Deadline(self.max_duration));
         }
-        debug!(self.logger, "describe the spot requests");
-        let mut desc_req = rusoto_ec2::DescribeSpotInstanceRequestsRequest::default();
-        desc_req.spot_instance_request_ids = Some(spot_instance_request_ids);
+        result
+    }
+
+    async fn tear_down<P: Provider>(
+        &self,
+        mut teardown: Teardown<'_, P>,
+        result: Result<(), Error>,
+    ) -> Result<(), Error> {
+        debug!(
+            self.logger,
+            "cleaning up the instances, security groups and key-pairs"
+        );
+        let released = teardown.release().await;
+
+        info!(self.logger, "burst done!");
+        match (result, released) {
+            (Err(e), Err(teardown_error)) => {
+                crit!(self.logger, "the teardown failed after the burst failed"; "error"=>?teardown_error);
+                Err(e)
+            }
+            (Err(e), Ok(())) => Err(e),
+            (Ok(()), Err(teardown_error)) => Err(Error::Teardown(Box::new(teardown_error))),
+            (Ok(()), Ok(())) => Ok(()),
+        }
+    }
+
+    ///Launch the machines and run the setups on them, recording them in the state of the run, and
+    ///return the cluster for the main script.
+    async fn launch_and_set_up<P>(
+        &self,
+        provider: &P,
+        run: &mut Run<'_, P>,
+        counts: &HashMap<String, i64>,
+    ) -> Result<Cluster, Error>
+    where
+        P: Provider,
+    {
+        // a setup order which can not be satisfied is refused before anything is launched
+        let dependencies = self
+            .descriptors
+            .iter()
+            .map(|(name, (setup, _))| (name.clone(), setup.dependencies.clone()))
+            .collect();
+        let phases = phases::phases(&dependencies)?;
+
+        // everything the run creates is named or tagged with its id, for the reaper
+        let run_id = run.state.run_id.clone();
+        let teardown = &mut run.teardown;
+        info!(self.logger, "launching the burst"; "run_id"=>&run_id);
 
-        let mut all_active;
-        let instance_ids: Vec<String> = loop {
-            let result = ec2
-                .describe_spot_instance_requests(desc_req.clone())
+        //Create the network of the run, if it has one of its own
+        let mut vpc_id = None;
+        let mut subnet_id = None;
+        if let Some(cidr) = &self.vpc_cidr {
+            trace!(self.logger, "creating a vpc"; "cidr"=>cidr);
+            let vpc = provider.create_vpc(cidr).await?;
+            teardown.vpc(&vpc);
+            // the gateway is torn down even if it is never attached or routed to
+            let gateway_id = provider.create_internet_gateway().await?;
+            teardown.internet_gateway(&gateway_id, Some(&vpc));
+            provider.attach_internet_gateway(&gateway_id, &vpc).await?;
+            provider
+                .route_to_internet_gateway(&vpc, &gateway_id)
+                .await?;
+            let subnet = provider.create_subnet(&vpc, cidr).await?;
+            teardown.subnet(&subnet);
+            provider
+                .tag_run(&[vpc.clone(), gateway_id, subnet.clone()], &run_id)
+                .await?;
+            trace!(self.logger, "vpc created"; "vpc_id"=>&vpc, "subnet_id"=>&subnet);
+            vpc_id = Some(vpc);
+            subnet_id = Some(subnet);
+        }
+        let placement_group = match self.placement {
+            Some(placement) => {
+                let name = format!("{}{}", provider::PLACEMENT_GROUP_PREFIX, run_id);
+                trace!(self.logger, "creating a placement group"; "name"=>&name, "strategy"=>placement.strategy());
+                provider
+                    .create_placement_group(&name, placement.strategy())
+                    .await?;
+                teardown.placement_group(&name);
+                Some(name)
+            }
+            None => None,
+        };
+
+        //Create a security group
+        let security_group_name = format!("{}{}", provider::SECURITY_GROUP_PREFIX, run_id);
+        trace!(self.logger,"Creating a secutiry group";"group_name"=>&security_group_name);
+        let group_id = provider
+            .create_security_group(
+                &security_group_name,
+                "Security group for burst",
+                vpc_id.as_deref(),
+            )
+            .await?;
+        teardown.security_group(&group_id);
+        provider
+            .tag_run(std::slice::from_ref(&group_id), &run_id)
+            .await?;
+
+        trace!(self.logger, "security group created";"group_id"=>&group_id.clone());
+
+        trace!(self.logger, "adding ip permissions to the security group");
+        let this_machine = if self
+            .ingress
+            .iter()
+            .any(|rule| rule.source == Source::ThisMachine)
+        {
+            let ip = tokio::task::spawn_blocking(network::public_ip)
                 .await
-                .map_err(failure::Error::from)
-                .map_err(|e| e.context("failed to describe spot instances for {}"))?;
-            let instance_requests = result.spot_instance_requests;
-            trace!(
-                self.logger,
-                "Checking the status of each spot instance request"
+                .map_err(|e| Error::io("look up the public ip of this machine", e.into()))??;
+            trace!(self.logger, "found the public ip of this machine"; "ip"=>%ip);
+            Some(ip)
+        } else {
+            None
+        };
+        let cluster_cidr = self
+            .vpc_cidr
+            .as_deref()
+            .unwrap_or(network::DEFAULT_VPC_CIDR);
+        let rules: Vec<provider::IngressRule> = self
+            .ingress
+            .iter()
+            .map(|rule| rule.resolve(cluster_cidr, this_machine))
+            .collect();
+        if !rules.is_empty() {
+            provider.authorize_ingress(&group_id, &rules).await?;
+        }
+
+        //Create key pairs for ssh
+        let key_name = format!("{}{}", provider::KEY_PAIR_PREFIX, run_id);
+        trace!(self.logger,"creating a key-pair";"key_name"=>&key_name);
+        let key_pair = provider.create_key_pair(&key_name).await?;
+        teardown.key_pair(&key_name);
+
+        trace!(self.logger,"key-pair generated"; "fingerprint"=>key_pair.fingerprint);
+
+        let key_pair_file = &run.key_file;
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(key_pair_file)
+            .and_then(|mut file| file.write_all(key_pair.material.as_bytes()))
+            .map_err(|e| {
+                Error::io(
+                    format!(
+                        "store the key-pair private key file : {}",
+                        key_pair_file.display()
+                    ),
+                    e,
+                )
+            })?;
+
+        trace!(self.logger,"key pair private key stored into disk";"path"=>?key_pair_file);
+        //prefixing ? prints the
+        //display version of the
+        //value
+
+        let launch_spec = |setup: &MachineSetup| provider::LaunchSpec {
+            ami: setup.ami.clone(),
+            instance_type: setup.instance_type.clone(),
+            security_group_id: group_id.clone(),
+            key_name: key_name.clone(),
+            subnet_id: subnet_id.clone(),
+            placement_group: placement_group.clone(),
+            client_token: provider::client_token(),
+        };
+
+        // 1. launch the instances, requesting the missing ones again as the setups allow
+        let mut id_to_name = HashMap::new();
+        let mut wanted = counts.clone();
+        let mut retries: HashMap<String, usize> = HashMap::new();
+        loop {
+            id_to_name.extend(
+                self.launch(provider, teardown, &run_id, &launch_spec, &wanted)
+                    .await?,
             );
-            let any_open = instance_requests
+            wanted = self
+                .descriptors
                 .iter()
-                .flatten()
-                .map(|it| (it, it.state.as_ref()))
-                .any(|(sir, state)| {
-                    if state.is_some_and(|s| s == "open")
-                        || (state.is_some_and(|s| s == "active") && sir.instance_id.is_none()) {
-                            true
-                    }
-                    else {
-                        trace!(self.logger, "spot instance request not yet ready";"state"=>state,"request"=>?sir);
-                        false
-                    }
-                });
-            if !any_open {
-                all_active = true;
-                break instance_requests
-                    .into_iter()
-                    .flatten()
-                    .filter_map(|r| {
-                        if r.state.as_ref().is_some_and(|s| s == "active") {
-                            let name = id_to_name.remove(
-                                &r.spot_instance_request_id
-                                    .expect("any spot instance request should have an id"),
-                            );
-                            id_to_name.insert(
-                                r.instance_id.as_ref().expect("in above code we ensured that no instance_id will be considred as not open").clone()
-                                ,name.expect(
-                                    "the name is proveded by us and we expect to have value",
-                                ),
-                            );
-                            Some(r.instance_id)
-                        } else {
-                            all_active = false;
-                            None
+                .filter_map(|(name, (setup, _))| {
+                    let number = &counts[name];
+                    let obtained = id_to_name.values().filter(|n| *n == name).count() as i64;
+                    let retried = retries.get(name).copied().unwrap_or(0);
+                    match setup.shortfall {
+                        ShortfallPolicy::Retry { rounds }
+                            if obtained < *number && retried < rounds =>
+                        {
+                            Some((name.clone(), number - obtained))
                         }
-                    })
-                    .filter_map(|instance_id| instance_id)
-                    .collect();
-            } else {
-                trace!(
-                    self.logger,
-                    "some spot instance requets are not ready yet, trying again ..."
-                );
-                use std::{thread, time::Duration};
-                thread::sleep(Duration::from_millis(200));
+                        _ => None,
+                    }
+                })
+                .collect();
+            if wanted.is_empty() {
+                break;
             }
-        };
+            for name in wanted.keys() {
+                *retries.entry(name.clone()).or_insert(0) += 1;
+            }
+            warn!(self.logger, "requesting the missing machines again"; "missing"=>?wanted);
+        }
 
-        //Stop spot requests
-        let mut cancel = rusoto_ec2::CancelSpotInstanceRequestsRequest::default();
-        cancel.spot_instance_request_ids = desc_req
-            .spot_instance_request_ids
-            .take()
-            .expect("the describe spot instance request should have spot request ids by now");
-        ec2.cancel_spot_instance_requests(cancel)
-            .await
-            .map_err(failure::Error::from)
-            .map_err(|e| e.context("failed to cancel spot instance request"))?;
+        let mut fulfillment = HashMap::new();
+        for (name, (setup, _)) in &self.descriptors {
+            let number = &counts[name];
+            let obtained = id_to_name.values().filter(|n| *n == name).count() as i64;
+            let outcome = Fulfillment {
+                requested: *number,
+                obtained,
+                retries: retries.get(name).copied().unwrap_or(0),
+            };
+            let minimum = match setup.shortfall {
+                ShortfallPolicy::Minimum(minimum) => minimum,
+                ShortfallPolicy::Fail | ShortfallPolicy::Retry { .. } => *number,
+            };
+            if obtained < minimum {
+                return Err(Shortfall {
+                    name: name.clone(),
+                    fulfillment: outcome,
+                }
+                .into());
+            }
+            if obtained < *number {
+                warn!(self.logger, "going on with fewer machines than requested for {}",name; "requested"=>number, "obtained"=>obtained);
+            }
+            fulfillment.insert(name.clone(), outcome);
+        }
+        let instance_ids: Vec<String> = id_to_name.keys().cloned().collect();
 
         // 2. wait for instances to come up
 
         let mut machines = HashMap::new();
 
-        let mut desc_instance_req = rusoto_ec2::DescribeInstancesRequest::default();
-        desc_instance_req.instance_ids = Some(instance_ids.clone());
-
-        let mut all_ready = false;
+        let mut delays = self.backoff.delays();
+        let waiting_since = Instant::now();
+        let mut all_ready = instance_ids.is_empty();
         while !all_ready {
-            all_ready = true;
             machines.clear();
+            let mut pending = Vec::new();
 
-            for reservation in ec2
-                .describe_instances(desc_instance_req.clone())
-                .await
-                .map_err(failure::Error::from)
-                .map_err(|e| e.context("failed to describe instances"))?
-                .reservations
-                .unwrap_or_else(Vec::new)
-            {
-                for instance in reservation.instances.unwrap_or_else(Vec::new) {
-                    match instance {
-                        rusoto_ec2::Instance {
-                            instance_id: Some(instance_id),
-                            instance_type: Some(instance_type),
-                            public_dns_name: Some(public_dns),
-                            private_ip_address: Some(private_ip),
-                            public_ip_address: Some(public_ip),
-                            ..
-                        } => {
-                            let name = id_to_name[&instance_id].clone();
-                            trace!(self.logger, "instance is ready"; "set"=>&name,"ip"=>&public_ip);
-                            machines.entry(name).or_insert_with(Vec::new).push(Machine {
+            for instance in provider.describe_instances(&instance_ids).await? {
+                match instance {
+                    provider::Instance {
+                        instance_id,
+                        instance_type: Some(instance_type),
+                        public_dns: Some(public_dns),
+                        private_ip: Some(private_ip),
+                        public_ip: Some(public_ip),
+                        availability_zone,
+                    } => {
+                        let name = id_to_name[&instance_id].clone();
+                        trace!(self.logger, "instance is ready"; "set"=>&name,"ip"=>&public_ip);
+                        let logger = self
+                            .logger
+                            .new(o!("machine"=>name.clone(), "ip"=>public_ip.clone()));
+                        machines
+                            .entry(name.clone())
+                            .or_insert_with(Vec::new)
+                            .push(Machine {
                                 ssh: None,
-                                private_ip,
-                                public_dns,
-                                _instance_type: instance_type,
-                                public_ip,
+                                logger,
+                                recorder: run.recorder.clone(),
+                                info: MachineInfo {
+                                    name,
+                                    index: 0,
+                                    instance_id,
+                                    instance_type,
+                                    availability_zone,
+                                    private_ip,
+                                    public_dns,
+                                    public_ip,
+                                },
                             });
-                        }
-                        _ => {
-                            all_ready = false;
-                            continue;
-                        }
+                    }
+                    provider::Instance { instance_id, .. } => {
+                        trace!(self.logger, "instance not yet ready";"instance_id"=>&instance_id);
+                        pending.push(instance_id);
                     }
                 }
-                if !all_ready {
-                    continue;
+            }
+
+            all_ready = pending.is_empty();
+            if all_ready {
+                for name in self.descriptors.keys() {
+                    machines.entry(name.clone()).or_default();
+                }
+                // the instances are described in no particular order
+                for group in machines.values_mut() {
+                    group.sort_by(|a, b| a.instance_id.cmp(&b.instance_id));
+                    for (index, machine) in group.iter_mut().enumerate() {
+                        machine.info.index = index;
+                    }
+                }
+            } else {
+                if waiting_since.elapsed() >= self.instance_timeout {
+                    return Err(Timeout {
+                        phase: WaitPhase::Instances,
+                        timeout: self.instance_timeout,
+                        pending,
+                    }
+                    .into());
                 }
+                time::sleep(delays.next().expect("the backoff delays never end")).await;
             }
         }
 
-        // defer!{{
-        //      let mut terminate_req = rusoto_ec2::TerminateInstancesRequest::default();
-        // terminate_req.instance_ids = instance_ids;
-        // tokio::task::spawn(async{
-        // while let Err(e) = ec2.terminate_instances(terminate_req.clone()).await {
-        //     let msg = format!("{}", e);
-        //     if msg.contains("Pooled stream disconnected") || msg.contains("broken pip") {
-        //         continue;
-        //     } else {
-        //         return Err::<(),failure::Error>(failure::Error::from(e)
-        //             .context("failed to terminate instances")
-        //             .into());
-        //     }
-        // }
-        // Ok(())});
-
-        // }}
-
-        //TODO: ensure the number of machines are the same as been requested
-
-        let mut errors = Vec::new();
-        if all_active {
-            for (name, machines) in &mut machines {
-                let descriptor = &self.descriptors[name];
-                let setup = &descriptor.0.setup;
-                errors.par_extend(
-                    machines
-                        .par_iter_mut()
-                        .map(|machine| -> Result<_, failure::Error> {
-                            let addr = {
-                                use std::net::{IpAddr, SocketAddr};
-
-                                SocketAddr::new(
-                                    IpAddr::from_str(&machine.public_ip)
-                                        .context("the machine public ip address is not valid")?,
-                                    22,
-                                )
-                            };
-                            let ssh = ssh::Session::connect(addr, key_pair_file.path())
-                                .map_err(failure::Error::from)
-                                .map_err(|e| {
-                                    e.context(format!(
-                                        "the ssh connection failed for {} to {}",
-                                        name, machine.public_dns
-                                    ))
-                                })?;
-                            machine.ssh = Some(ssh);
+        // 3. learn the host keys the machines are to present, if they come from the console
+        let host_keys = match self.host_key_policy {
+            HostKeyPolicy::ConsoleOutput => {
+                self.wait_for_host_keys(provider, &instance_ids).await?
+            }
+            HostKeyPolicy::TrustOnFirstUse | HostKeyPolicy::AcceptAny => HashMap::new(),
+        };
+
+        let infos: Arc<HashMap<String, Vec<MachineInfo>>> = Arc::new(
+            machines
+                .iter()
+                .map(|(name, group)| {
+                    let group = group.iter().map(|machine| machine.info.clone()).collect();
+                    (name.clone(), group)
+                })
+                .collect(),
+        );
+        run.state.machines = (*infos).clone();
+        run.state.fulfillment = fulfillment.clone();
+
+        let run = &*run;
+        self.within_deadline(watchdog::run_until(
+            provider,
+            &self.logger,
+            &instance_ids,
+            run.deadline,
+            || {
+                // the machines of the setups of a phase are set up at the same time, and a failed
+                // phase leaves the setups depending on it alone
+                for phase in &phases {
+                    trace!(self.logger, "setting up a phase"; "setups"=>?phase);
+                    let in_phase: Vec<(&String, &mut Machine)> = machines
+                        .iter_mut()
+                        .filter(|(name, _)| phase.contains(name))
+                        .flat_map(|(name, group)| group.iter_mut().map(move |machine| (name, machine)))
+                        .collect();
+                    let errors: Vec<SetupFailure> = in_phase.into_par_iter().filter_map(|(name, machine)| {
+                        let setup = &self.descriptors[name].0.setup;
+                        let mut set_up = || -> Result<(), Error> {
+                            machine.ssh = Some(self.connect(provider, run, machine, &host_keys)?);
                             debug!(self.logger,"setting up the instance for {}",&name;"ip"=>&machine.public_ip);
-                            setup(machine.ssh.as_mut().expect("the ssh has value"))
-                                .map_err(failure::Error::from)
-                                .map_err(|e| {
-                                    e.context(format!(
-                                        "setup procedure for {} on {} failed",
-                                        name, machine.public_dns
-                                    ))
-                                })?;
+                            let topology = Topology::new(infos.clone(), name, machine.index);
+                            setup(
+                                machine.ssh.as_mut().expect("the ssh has value"),
+                                &topology,
+                            )
+                            .map_err(Error::Closure)?;
                             trace!(self.logger,"finish setting up for {}",&name;"ip"=>&machine.public_ip);
                             Ok(())
+                        };
+                        let error = set_up().err()?;
+                        error!(self.logger, "the setup failed for {}",name; "ip"=>&machine.public_ip, "error"=>%error);
+                        Some(SetupFailure {
+                            name: name.clone(),
+                            public_ip: machine.public_ip.clone(),
+                            error,
                         })
-                        .filter_map(Result::err),
-                );
+                    }).collect();
+                    if !errors.is_empty() {
+                        return Err(Error::Setup(errors));
+                    }
+                }
+                Ok(Cluster::new(machines, fulfillment, run.recorder.clone()))
+            },
+        ))
+    }
+
+    ///Connect to the machines of the cluster attached to, as they were recorded by the run which
+    ///launched it, and return the cluster for the main script.
+    async fn attach<P>(&self, provider: &P, run: &mut Run<'_, P>) -> Result<Cluster, Error>
+    where
+        P: Provider,
+    {
+        let run = &*run;
+        let instance_ids: Vec<String> = run
+            .state
+            .machines
+            .values()
+            .flatten()
+            .map(|info| info.instance_id.clone())
+            .collect();
+        let host_keys = match self.host_key_policy {
+            HostKeyPolicy::ConsoleOutput => {
+                self.wait_for_host_keys(provider, &instance_ids).await?
             }
-        }
+            HostKeyPolicy::TrustOnFirstUse | HostKeyPolicy::AcceptAny => HashMap::new(),
+        };
 
-        if errors.is_empty() {
-            info!(self.logger, "running the burst");
-            let start = time::Instant::now();
-            script(machines)
-                .context("main procedure failed")
-                .map_err(|e| {
-                    crit!(self.logger,"Error happend during runing the main procedure";"error"=>?e);
-                    e
-                })?;
-            info!(self.logger,"the burst run it finished";"took"=>?start.elapsed());
+        let mut machines: HashMap<String, Vec<Machine>> = run
+            .state
+            .machines
+            .iter()
+            .map(|(name, group)| {
+                let group = group
+                    .iter()
+                    .map(|info| Machine {
+                        ssh: None,
+                        logger: self
+                            .logger
+                            .new(o!("machine"=>name.clone(), "ip"=>info.public_ip.clone())),
+                        info: info.clone(),
+                        recorder: run.recorder.clone(),
+                    })
+                    .collect();
+                (name.clone(), group)
+            })
+            .collect();
+        let errors: Vec<SetupFailure> = machines
+            .values_mut()
+            .flatten()
+            .collect::<Vec<&mut Machine>>()
+            .into_par_iter()
+            .filter_map(|machine| {
+                let error = match self.connect(provider, run, machine, &host_keys) {
+                    Ok(ssh) => {
+                        machine.ssh = Some(ssh);
+                        return None;
+                    }
+                    Err(error) => error,
+                };
+                error!(self.logger, "failed to reconnect to the machine"; "ip"=>&machine.public_ip, "error"=>%error);
+                Some(SetupFailure {
+                    name: machine.name.clone(),
+                    public_ip: machine.public_ip.clone(),
+                    error,
+                })
+            })
+            .collect();
+        if !errors.is_empty() {
+            return Err(Error::Setup(errors));
         }
-        // 5. terminate all instances
-
-        let mut terminate_req = rusoto_ec2::TerminateInstancesRequest::default();
-        terminate_req.instance_ids = instance_ids;
-        while let Err(e) = ec2.terminate_instances(terminate_req.clone()).await {
-            let msg = format!("{}", e);
-            if msg.contains("Pooled stream disconnected") || msg.contains("broken pip") {
-                trace!(self.logger, "retrying the termination instance requests";"message"=>?msg);
-                continue;
-            } else {
-                return Err(failure::Error::from(e)
-                    .context("failed to terminate instances")
-                    .into());
+        Ok(Cluster::new(
+            machines,
+            run.state.fulfillment.clone(),
+            run.recorder.clone(),
+        ))
+    }
+
+    ///Connect to a machine with the connect options of its setup, checking its host key as the
+    ///policy says, and make it shut itself down at the deadline of the run.
+    fn connect<P>(
+        &self,
+        provider: &P,
+        run: &Run<'_, P>,
+        machine: &Machine,
+        host_keys: &HashMap<String, Vec<String>>,
+    ) -> Result<ssh::Session, Error>
+    where
+        P: Provider,
+    {
+        let options = self
+            .descriptors
+            .get(&machine.name)
+            .map(|(setup, _)| setup.connect.clone())
+            .unwrap_or_default();
+        let connect = ssh::ConnectOptions {
+            host_key: match self.host_key_policy {
+                HostKeyPolicy::TrustOnFirstUse => {
+                    ssh::HostKeyCheck::TrustOnFirstUse(run.known_hosts.clone())
+                }
+                HostKeyPolicy::ConsoleOutput => {
+                    ssh::HostKeyCheck::Keys(host_keys[&machine.instance_id].clone())
+                }
+                HostKeyPolicy::AcceptAny => ssh::HostKeyCheck::AcceptAny,
+            },
+            ..options
+        };
+        let ssh = provider.connect(
+            &machine.instance_id,
+            &machine.public_ip,
+            &run.key_file,
+            &connect,
+        )?;
+        provider.schedule_shutdown(&machine.instance_id, &ssh, run.deadline)?;
+        Ok(ssh)
+    }
+
+    ///Poll the console output of the instances until each lists its ssh host keys, and return
+    ///the keys of every instance.
+    async fn wait_for_host_keys<P>(
+        &self,
+        provider: &P,
+        instance_ids: &[String],
+    ) -> Result<HashMap<String, Vec<String>>, Error>
+    where
+        P: Provider,
+    {
+        let mut host_keys = HashMap::new();
+        let mut delays = self.backoff.delays();
+        let waiting_since = Instant::now();
+        let mut pending = instance_ids.to_vec();
+        loop {
+            for id in std::mem::take(&mut pending) {
+                let output = provider.console_output(&id).await?.unwrap_or_default();
+                let keys = ssh::keys_from_console(&output);
+                if keys.is_empty() {
+                    trace!(self.logger, "host keys not yet in the console output";"instance_id"=>&id);
+                    pending.push(id);
+                } else {
+                    trace!(self.logger, "host keys found"; "instance_id"=>&id, "keys"=>keys.len());
+                    host_keys.insert(id, keys);
+                }
             }
+            if pending.is_empty() {
+                return Ok(host_keys);
+            }
+            if waiting_since.elapsed() >= self.host_key_timeout {
+                return Err(Timeout {
+                    phase: WaitPhase::HostKeys,
+                    timeout: self.host_key_timeout,
+                    pending,
+                }
+                .into());
+            }
+            time::sleep(delays.next().expect("the backoff delays never end")).await;
         }
+    }
 
-        debug!(self.logger, "cleaning up the security groups and key-pairs");
+    ///Launch the given number of machines for each setup, waiting for their spot requests to be
+    ///fulfilled, and return the setup name of every instance obtained.
+    async fn launch<P>(
+        &self,
+        provider: &P,
+        teardown: &mut Teardown<'_, P>,
+        run_id: &str,
+        launch_spec: &impl Fn(&MachineSetup) -> provider::LaunchSpec,
+        counts: &HashMap<String, i64>,
+    ) -> Result<HashMap<String, String>, Error>
+    where
+        P: Provider,
+    {
+        let mut id_to_name = HashMap::new();
+        let mut instance_ids = Vec::new();
+        let mut spot_instance_request_ids = Vec::new();
+        debug!(self.logger, "issuing the spot requests");
+        for (name, number) in counts {
+            let setup = &self.descriptors[name].0;
+            let max_price = match setup.purchase {
+                PurchaseOption::OnDemand => {
+                    trace!(self.logger, "launching on-demand instances for {}",name; "number"=>number);
+                    let launched = provider.run_instances(&launch_spec(setup), *number).await?;
+                    teardown.instances(&launched);
+                    provider.tag_run(&launched, run_id).await?;
+                    for id in launched {
+                        id_to_name.insert(id.clone(), name.clone());
+                        instance_ids.push(id);
+                    }
+                    continue;
+                }
+                PurchaseOption::Spot { max_price }
+                | PurchaseOption::SpotWithFallback { max_price } => max_price,
+            };
 
-        //Clean up security group and key-pairs
-        let req = rusoto_ec2::DeleteSecurityGroupRequest {
-            group_id: Some(group_id.clone()),
-            ..Default::default()
-        };
+            trace!(self.logger, "issuing spot request for {}",name; "number"=>number);
+            let requests = provider
+                .request_spot_instances(&launch_spec(setup), *number, max_price)
+                .await?;
+            teardown.spot_requests(&requests);
+            provider.tag_run(&requests, run_id).await?;
 
-        let mut retries = 0;
+            spot_instance_request_ids.extend(requests.into_iter().inspect(|it| {
+                trace!(self.logger,"spot request issued for {}",name; "spot_instance_request_id"=>it.clone());
+                id_to_name.insert(it.clone(), name.clone());
+            }));
+        }
+        debug!(self.logger, "describe the spot requests");
 
-        loop {
-            match ec2.delete_security_group(req.clone()).await {
-                Ok(_) => break,
-                Err(e) if retries < 5 => {
-                    debug!(self.logger,"deleting the secuity group failed, retrying";"error"=>?e);
-                    std::thread::sleep(std::time::Duration::from_secs(10 * retries + 1));
-                    retries += 1;
+        // the number of on-demand instances to launch per setup for lack of spot capacity
+        let mut shortfall = HashMap::new();
+        let mut delays = self.backoff.delays();
+        let waiting_since = Instant::now();
+        while !spot_instance_request_ids.is_empty() {
+            let instance_requests = provider
+                .describe_spot_instance_requests(&spot_instance_request_ids)
+                .await?;
+            trace!(
+                self.logger,
+                "Checking the status of each spot instance request"
+            );
+            let pending: Vec<String> = instance_requests
+                .iter()
+                .filter(|sir| {
+                    let falls_back = self.descriptors[&id_to_name[&sir.id]].0.falls_back();
+                    (sir.state == "open" && !(falls_back && sir.lacks_capacity()))
+                        || (sir.state == "active" && sir.instance_id.is_none())
+                })
+                .inspect(|sir| {
+                    trace!(self.logger, "spot instance request not yet ready";"state"=>&sir.state,"request"=>?sir);
+                })
+                .map(|sir| sir.id.clone())
+                .collect();
+            let timed_out = waiting_since.elapsed() >= self.spot_request_timeout;
+            if timed_out
+                && pending.iter().any(|id| {
+                    self.descriptors[&id_to_name[id]].0.shortfall == ShortfallPolicy::Fail
+                })
+            {
+                return Err(Timeout {
+                    phase: WaitPhase::SpotRequests,
+                    timeout: self.spot_request_timeout,
+                    pending,
                 }
-                Err(e) => {
-                    return Err(failure::Error::from(e)
-                        .context("failed to remove the security group")
-                        .into());
+                .into());
+            }
+            if pending.is_empty() || timed_out {
+                if timed_out {
+                    warn!(self.logger, "giving up on the spot requests which are not fulfilled yet"; "requests"=>?pending);
+                }
+                for r in instance_requests {
+                    let name = id_to_name
+                        .remove(&r.id)
+                        .expect("the name is proveded by us and we expect to have value");
+                    match r.instance_id {
+                        Some(instance_id) if r.state == "active" => {
+                            id_to_name.insert(instance_id.clone(), name);
+                            instance_ids.push(instance_id);
+                        }
+                        // the missing machines are left to the shortfall policy of the setup
+                        _ if self.descriptors[&name].0.falls_back() => {
+                            *shortfall.entry(name).or_insert(0) += 1;
+                        }
+                        _ => {}
+                    }
                 }
+                break;
+            } else {
+                trace!(
+                    self.logger,
+                    "some spot instance requets are not ready yet, trying again ..."
+                );
+                time::sleep(delays.next().expect("the backoff delays never end")).await;
             }
         }
-        debug!(self.logger, "deleting the security group finished";"group_id"=>group_id);
-        let req = rusoto_ec2::DeleteKeyPairRequest {
-            key_name: Some(key_name.clone()),
-            ..Default::default()
-        };
-        debug!(self.logger, "deleting the key-pair";"key_name"=>&key_name);
-        ec2.delete_key_pair(req)
-            .await
-            .context("failed to remove the key-pair")?;
 
-        info!(self.logger, "burst done!");
-        errors.into_iter().next().map(|e| Err(e)).unwrap_or(Ok(()))
+        // the instances launched for spot requests do not inherit their tags
+        teardown.instances(&instance_ids);
+        provider.tag_run(&instance_ids, run_id).await?;
+
+        //Stop spot requests
+        teardown.cancel_spot_requests().await?;
+
+        for (name, number) in shortfall {
+            warn!(self.logger, "no spot capacity for {}, launching on-demand instances instead",name; "number"=>number);
+            let launched = provider
+                .run_instances(&launch_spec(&self.descriptors[&name].0), number)
+                .await?;
+            teardown.instances(&launched);
+            provider.tag_run(&launched, run_id).await?;
+            for id in launched {
+                id_to_name.insert(id.clone(), name.clone());
+                instance_ids.push(id);
+            }
+        }
+
+        Ok(id_to_name)
     }
 }
 
+//The error of an iteration whose script panicked, with the message of the panic.
+fn panicked(panic: Box<dyn std::any::Any + Send>) -> String {
+    let message = panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("no message");
+    format!("the script panicked: {}", message)
+}
+
 ///A handle to access the ec2 instance vlaues or configuations such as public ip, host name or
 ///private ip
+///
+///It dereferences to its [`MachineInfo`], so `vm.private_ip` or `vm.instance_type` work
+///directly.
 //#[derive(Debug)]
 pub struct Machine {
     ssh: Option<ssh::Session>,
-    _instance_type: String,
-    ///provides the private ip of the ec2 istance.
-    pub private_ip: String,
-    ///provides the public host name of the ec2 instance.
-    pub public_dns: String,
-    ///provide tge public ip of the ec2 instance
-    pub public_ip: String,
+    logger: Logger,
+    info: MachineInfo,
+    recorder: report::Recorder,
+}
+
+impl std::ops::Deref for Machine {
+    type Target = MachineInfo;
+    fn deref(&self) -> &MachineInfo {
+        &self.info
+    }
 }
 
 impl Machine {
-    ///run a procedure on an ec2 instance machine handler
-    pub fn run(&self, command: &str) -> Result<String, failure::Error> {
-        if let Some(ssh) = &self.ssh {
-            return ssh.cmd(command);
-        }
+    ///run a procedure on an ec2 instance machine handler, and return its standard output. It
+    ///fails with [`Error::Exit`] unless the command exits with status 0.
+    pub fn run(&self, command: &str) -> Result<String, Error> {
+        self.session(command)?.cmd(command)
+    }
+
+    ///Run a command and return its exit status, standard output, standard error and duration,
+    ///whatever the exit status is.
+    pub fn exec(&self, command: &str) -> Result<ssh::Output, Error> {
+        self.session(command)?.exec(command)
+    }
+
+    ///Run a command on the machine without blocking the runtime, so that many machines can be
+    ///driven concurrently from an async script.
+    pub async fn run_async(&self, command: &str) -> Result<String, Error> {
+        self.session(command)?.cmd_async(command).await
+    }
+
+    ///Like [`Machine::exec`], without blocking the runtime.
+    pub async fn exec_async(&self, command: &str) -> Result<ssh::Output, Error> {
+        self.session(command)?.exec_async(command).await
+    }
+
+    ///Reboot the machine and wait for it to be back, until `timeout` has passed since the reboot;
+    ///see [`ssh::Session::reboot_and_wait`].
+    pub fn reboot_and_wait(&self, timeout: Duration) -> Result<(), Error> {
+        info!(self.logger, "rebooting the machine");
+        self.session("sudo shutdown -r now")?
+            .reboot_and_wait(timeout)?;
+        info!(self.logger, "the machine is back");
+        Ok(())
+    }
+
+    ///Copy a local file or directory, with its permissions, to the machine.
+    ///```rust, no_run
+    /// # fn f(vm: &burst::Machine) -> Result<(), burst::Error> {
+    /// vm.upload("target/release/server", "server")?;
+    /// vm.run("./server --self-test")?;
+    /// vm.download("results", "results/server")?;
+    /// # Ok(())
+    /// # }
+    ///```
+    pub fn upload<L: AsRef<Path>, R: AsRef<Path>>(&self, local: L, remote: R) -> Result<(), Error> {
+        let remote = remote.as_ref();
+        self.session(&remote.display().to_string())?
+            .upload(local, remote)
+    }
+
+    ///Copy a file or directory of the machine, with its permissions, to the local disk.
+    pub fn download<R: AsRef<Path>, L: AsRef<Path>>(
+        &self,
+        remote: R,
+        local: L,
+    ) -> Result<(), Error> {
+        let remote = remote.as_ref();
+        self.session(&remote.display().to_string())?
+            .download(remote, local)
+    }
+
+    ///Start a command in the background, handing every line of its output to `on_line` as it
+    ///arrives. The returned process can be signalled, killed and waited for.
+    ///```rust, no_run
+    /// # use burst::ssh::Stream;
+    /// # fn f(vm: &burst::Machine) -> Result<(), burst::Error> {
+    /// let server = vm.spawn("./server --port 9000", |stream, line| {
+    ///     if stream == Stream::Stderr {
+    ///         eprintln!("server: {}", line);
+    ///     }
+    /// })?;
+    /// // run the clients ...
+    /// let status = server.kill()?;
+    /// # Ok(())
+    /// # }
+    ///```
+    pub fn spawn<F>(&self, command: &str, on_line: F) -> Result<ssh::Process, Error>
+    where
+        F: FnMut(ssh::Stream, &str) + Send + 'static,
+    {
+        self.session(command)?.spawn(command, on_line)
+    }
+
+    ///Start a command in the background, logging its output to the burst logger.
+    pub fn spawn_logged(&self, command: &str) -> Result<ssh::Process, Error> {
+        self.session(command)?.spawn_logged(command, &self.logger)
+    }
+
+    ///Record a metric measured on this machine in the report of the run.
+    ///```rust, no_run
+    /// # fn f(vm: &burst::Machine) -> Result<(), burst::Error> {
+    /// let output = vm.exec("./client --requests 10000")?;
+    /// vm.record("requests_per_sec", 10000.0 / output.duration.as_secs_f64());
+    /// # Ok(())
+    /// # }
+    ///```
+    pub fn record(&self, name: &str, value: f64) {
+        self.recorder.record(name, value, Some(&self.info));
+    }
+
+    fn session(&self, command: &str) -> Result<&ssh::Session, Error> {
+        self.ssh.as_ref().ok_or_else(|| Error::Command {
+            command: command.to_string(),
+            source: "the ssh for the machin is not initilized".into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use provider::Fake;
+    use testing::{builder, idle, run_torn_down};
+    use tokio::runtime::Runtime;
+
+    //An endpoint which answers a single request with an error, and the head of that request.
+    fn endpoint() -> (String, std::thread::JoinHandle<String>) {
+        use std::io::{BufRead, BufReader};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let request = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut head = String::new();
+            // the head ends with an empty line
+            while reader.read_line(&mut head).unwrap() > 2 {}
+            reader
+                .get_mut()
+                .write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            head
+        });
+        (endpoint, request)
+    }
+
+    //Ask the ec2 client the builder connects with for the spot prices.
+    fn ask_ec2(builder: &BurstBuilder) {
+        let rt = Runtime::new().unwrap();
+        let asked =
+            rt.block_on(async { builder.ec2()?.spot_prices(&["t2.micro".to_string()]).await });
+        assert!(asked.is_err());
+    }
+
+    #[test]
+    fn custom_endpoint_and_credentials_reach_the_client() {
+        let (endpoint, request) = endpoint();
+        let mut builder = BurstBuilder::default();
+        builder.set_endpoint("eu-local-1", &endpoint);
+        builder.use_credentials(credential::StaticProvider::new_minimal(
+            "AKIDSTATIC".to_string(),
+            "secret".to_string(),
+        ));
+
+        ask_ec2(&builder);
+        let request = request.join().unwrap();
+        assert!(request.contains("Credential=AKIDSTATIC/"));
+        assert!(request.contains("/eu-local-1/ec2/aws4_request"));
+    }
+
+    #[test]
+    fn region_and_profile_reach_the_client() {
+        let dir = tempfile::tempdir().unwrap();
+        let credentials = dir.path().join("credentials");
+        std::fs::write(
+            &credentials,
+            "[bench]\naws_access_key_id = AKIDPROFILE\naws_secret_access_key = secret\n",
+        )
+        .unwrap();
+        // SAFETY: no other test reads or writes the environment variables of rusoto
+        unsafe { std::env::set_var("AWS_SHARED_CREDENTIALS_FILE", &credentials) };
+        let (endpoint, request) = endpoint();
+        let mut builder = BurstBuilder::default();
+        builder.set_region(Region::Custom {
+            name: "eu-west-3".to_string(),
+            endpoint,
+        });
+        builder.use_profile("bench");
+
+        ask_ec2(&builder);
+        let request = request.join().unwrap();
+        assert!(request.contains("Credential=AKIDPROFILE/"));
+        assert!(request.contains("/eu-west-3/ec2/aws4_request"));
+    }
+
+    #[test]
+    fn shortfall_is_retried_before_failing() {
+        let mut builder = builder(&[]);
+        builder.add_setup(
+            "server".to_string(),
+            2,
+            MachineSetup::new("p3.16xlarge", "ami-fake", |_| Ok(()))
+                .with_shortfall_policy(ShortfallPolicy::Retry { rounds: 2 }),
+        );
+        let fake = Fake::new().reject_spot("p3.16xlarge");
+
+        let error = run_torn_down(
+            &fake,
+            builder.run_with(&fake, |_| panic!("the script must not run")),
+        )
+        .unwrap_err();
 
-        Err(failure::Context::from("the ssh for the machin is not initilized").into())
+        let Error::Shortfall(shortfall) = error else {
+            panic!("expected a shortfall, got {}", error);
+        };
+        assert_eq!(shortfall.fulfillment.retries, 2);
+    }
+
+    #[test]
+    fn minimum_policy_goes_on_with_fewer_machines() {
+        let mut builder = builder(&[("server", 1)]);
+        builder.set_wait_timeout(WaitPhase::SpotRequests, Duration::from_millis(100));
+        builder.add_setup(
+            "client".to_string(),
+            2,
+            MachineSetup::new("c5.24xlarge", "ami-fake", |_| Ok(()))
+                .with_shortfall_policy(ShortfallPolicy::Minimum(0)),
+        );
+        let fake = Fake::new().without_spot_capacity("c5.24xlarge");
+
+        run_torn_down(
+            &fake,
+            builder.run_with(&fake, |vms| {
+                assert_eq!(vms["server"].len(), 1);
+                assert!(vms["client"].is_empty());
+                assert_eq!(
+                    vms.fulfillment("client"),
+                    Some(&Fulfillment {
+                        requested: 2,
+                        obtained: 0,
+                        retries: 0
+                    })
+                );
+                Ok(())
+            }),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn setups_and_script_run_on_every_machine() {
+        let mut builder = builder(&[]);
+        builder.add_setup(
+            "server".to_string(),
+            1,
+            MachineSetup::new("t2.micro", "ami-fake", |ssh| {
+                ssh.cmd("echo server > role")?;
+                Ok(())
+            }),
+        );
+        builder.add_setup(
+            "client".to_string(),
+            3,
+            MachineSetup::new("t2.micro", "ami-fake", |ssh| {
+                ssh.cmd("echo client > role")?;
+                Ok(())
+            }),
+        );
+        let fake = Fake::new().with_open_polls(2);
+
+        run_torn_down(
+            &fake,
+            builder.run_with(&fake, |vms| {
+                assert_eq!(vms["server"].len(), 1);
+                assert_eq!(vms["client"].len(), 3);
+                assert_eq!(vms["server"][0].run("cat role")?, "server\n");
+                for client in &vms["client"] {
+                    assert_eq!(client.run("cat role")?, "client\n");
+                }
+                Ok(())
+            }),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn spot_burst_runs_on_local_sandboxes() {
+        let mut builder = builder(&[]);
+        builder.add_setup(
+            "server".to_string(),
+            1,
+            MachineSetup::new("t2.micro", "ami-local", |ssh| {
+                ssh.cmd("echo ready > state")?;
+                Ok(())
+            }),
+        );
+        let local = provider::Local::new().with_sandboxes(1);
+
+        let rt = Runtime::new().unwrap();
+        rt.block_on(builder.run_with(&local, |vms| {
+            assert_eq!(vms["server"][0].run("cat state")?, "ready\n");
+            Ok(())
+        }))
+        .unwrap();
+    }
+
+    #[test]
+    fn reboot_schedules_the_shutdown_again() {
+        let mut builder = builder(&[("server", 1)]);
+        let fake = Fake::new();
+
+        run_torn_down(
+            &fake,
+            builder.run_with(&fake, |vms| {
+                let server = &vms["server"][0];
+                assert_eq!(fake.shutdown_schedules(&server.instance_id).len(), 1);
+                server.reboot_and_wait(Duration::from_secs(60))?;
+                assert_eq!(fake.shutdown_schedules(&server.instance_id).len(), 2);
+                Ok(())
+            }),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn spot_without_capacity_falls_back_to_on_demand() {
+        let mut builder = builder(&[]);
+        builder.add_setup(
+            "server".to_string(),
+            1,
+            MachineSetup::new("m5.large", "ami-fake", |_| Ok(()))
+                .with_purchase_option(PurchaseOption::OnDemand),
+        );
+        builder.add_setup(
+            "client".to_string(),
+            2,
+            MachineSetup::new("c5.24xlarge", "ami-fake", |_| Ok(())).with_purchase_option(
+                PurchaseOption::SpotWithFallback {
+                    max_price: Some(1.5),
+                },
+            ),
+        );
+        let fake = Fake::new().without_spot_capacity("c5.24xlarge");
+
+        run_torn_down(
+            &fake,
+            builder.run_with(&fake, |vms| {
+                assert_eq!(vms["server"].len(), 1);
+                assert_eq!(vms["client"].len(), 2);
+                Ok(())
+            }),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn async_script_drives_machines_concurrently() {
+        let mut builder = builder(&[("client", 3)]);
+        let fake = Fake::new();
+
+        run_torn_down(
+            &fake,
+            builder.run_with_async(&fake, |vms| async move {
+                let start = Instant::now();
+                let clients = &vms["client"];
+                let (a, b, c) = tokio::join!(
+                    clients[0].run_async("sleep 0.5; echo a"),
+                    clients[1].run_async("sleep 0.5; echo b"),
+                    clients[2].run_async("sleep 0.5; echo c"),
+                );
+                assert_eq!(a? + &b? + &c?, "a\nb\nc\n");
+                assert!(start.elapsed() < Duration::from_millis(1400));
+                Ok(())
+            }),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn setups_see_the_machines_of_the_other_setups() {
+        let mut builder = builder(&[("server", 1)]);
+        builder.add_setup(
+            "client".to_string(),
+            2,
+            MachineSetup::new_with_topology("t2.micro", "ami-fake", |ssh, topology| {
+                assert_eq!(topology.this().name, "client");
+                assert_eq!(topology.machines("client").len(), 2);
+                assert!(topology.machines("missing").is_empty());
+                let server = topology.private_ips("server")[0];
+                ssh.cmd(&format!(
+                    "echo {} {} > server",
+                    server,
+                    topology.this().index
+                ))?;
+                Ok(())
+            }),
+        );
+        let fake = Fake::new();
+
+        run_torn_down(
+            &fake,
+            builder.run_with(&fake, |vms| {
+                let server = vms.private_ips("server")[0];
+                for (index, client) in vms["client"].iter().enumerate() {
+                    assert_eq!(client.index, index);
+                    assert_eq!(client.name, "client");
+                    assert_eq!(client.instance_type, "t2.micro");
+                    assert!(client.availability_zone.is_some());
+                    assert_eq!(client.run("cat server")?, format!("{} {}\n", server, index));
+                }
+                Ok(())
+            }),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn setups_wait_for_their_dependencies() {
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut builder = builder(&[]);
+        let server_events = events.clone();
+        builder.add_setup(
+            "server".to_string(),
+            2,
+            MachineSetup::new("t2.micro", "ami-fake", move |_| {
+                std::thread::sleep(Duration::from_millis(200));
+                server_events.lock().unwrap().push("server");
+                Ok(())
+            }),
+        );
+        let client_events = events.clone();
+        builder.add_setup(
+            "client".to_string(),
+            2,
+            MachineSetup::new("t2.micro", "ami-fake", move |_| {
+                client_events.lock().unwrap().push("client");
+                Ok(())
+            })
+            .with_dependency("server"),
+        );
+        builder.add_setup("loop".to_string(), 1, idle().with_dependency("loop"));
+        let fake = Fake::new();
+
+        // nothing is launched for a setup order which can not be satisfied
+        let error = run_torn_down(&fake, builder.run_with(&fake, |_| Ok(()))).unwrap_err();
+        assert!(matches!(error, Error::Dependencies(_)));
+
+        builder.descriptors.remove("loop");
+        run_torn_down(&fake, builder.run_with(&fake, |_| Ok(()))).unwrap();
+        assert_eq!(
+            *events.lock().unwrap(),
+            ["server", "server", "client", "client"]
+        );
+    }
+
+    #[test]
+    fn dedicated_network_is_set_up_and_torn_down() {
+        let mut builder = builder(&[("server", 2)]);
+        builder.use_dedicated_vpc("10.0.0.0/16");
+        builder.use_placement_group(Placement::Cluster);
+        builder.add_ingress_rule(Ingress::udp(9000..=9100, Source::Cluster));
+        let fake = Fake::new();
+
+        run_torn_down(
+            &fake,
+            builder.run_with(&fake, |vms| {
+                assert_eq!(vms["server"].len(), 2);
+                // the vpc, its internet gateway and subnet, and the placement group
+                assert_eq!(fake.network_resources().len(), 4);
+                let rules = fake.ingress_rules();
+                assert!(rules.contains(&provider::IngressRule {
+                    protocol: "udp".to_string(),
+                    from_port: 9000,
+                    to_port: 9100,
+                    cidr: "10.0.0.0/16".to_string(),
+                }));
+                assert!(rules.iter().any(|rule| rule.from_port == 22));
+                Ok(())
+            }),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn network_is_torn_down_when_it_can_not_be_routed() {
+        let mut builder = builder(&[("server", 1)]);
+        builder.use_dedicated_vpc("10.0.0.0/16");
+        let fake = Fake::new().without_routes();
+
+        let error = run_torn_down(
+            &fake,
+            builder.run_with(&fake, |_| panic!("the script must not run")),
+        )
+        .unwrap_err();
+        assert!(matches!(error, Error::Provider { .. }));
+    }
+
+    #[test]
+    fn resources_are_tagged_with_the_run() {
+        let mut builder = builder(&[]);
+        builder.use_dedicated_vpc("10.0.0.0/16");
+        builder.use_placement_group(Placement::Spread);
+        builder.add_setup(
+            "server".to_string(),
+            1,
+            idle().with_purchase_option(PurchaseOption::OnDemand),
+        );
+        builder.add_setup("client".to_string(), 2, idle());
+        let fake = Fake::new();
+
+        run_torn_down(
+            &fake,
+            builder.run_with_async(&fake, |_| async {
+                let resources = fake.list_run_resources().await?;
+                // the instances, the security group, the key pair and the network of the run
+                assert_eq!(resources.len(), 9);
+                assert!(resources.iter().all(|r| r.run_id == resources[0].run_id));
+                let instances = resources
+                    .iter()
+                    .filter(|r| r.kind == provider::ResourceKind::Instance)
+                    .count();
+                assert_eq!(instances, 3);
+                Ok(())
+            }),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn host_keys_are_taken_from_the_console_output() {
+        let mut builder = builder(&[("server", 2)]);
+        builder.set_host_key_policy(HostKeyPolicy::ConsoleOutput);
+        let fake = Fake::new();
+
+        run_torn_down(
+            &fake,
+            builder.run_with(&fake, |vms| {
+                assert_eq!(vms["server"].len(), 2);
+                Ok(())
+            }),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn waiting_phases_time_out() {
+        let fake = Fake::new().without_spot_capacity("c5.24xlarge");
+        let mut builder = builder(&[]);
+        builder.set_wait_timeout(WaitPhase::SpotRequests, Duration::from_millis(100));
+        builder.set_backoff(Backoff {
+            initial: Duration::from_millis(10),
+            max: Duration::from_millis(20),
+            factor: 2,
+        });
+        builder.add_setup(
+            "client".to_string(),
+            2,
+            MachineSetup::new("c5.24xlarge", "ami-fake", |_| Ok(())),
+        );
+
+        let error = run_torn_down(&fake, builder.run_with(&fake, |_| Ok(()))).unwrap_err();
+        let Error::Timeout(timeout) = error else {
+            panic!("expected a timeout, got {}", error);
+        };
+        assert_eq!(timeout.phase, WaitPhase::SpotRequests);
+        assert_eq!(timeout.pending.len(), 2);
+
+        let fake = Fake::new().with_boot_polls(usize::MAX);
+        builder.set_wait_timeout(WaitPhase::Instances, Duration::from_millis(100));
+        let error = run_torn_down(&fake, builder.run_with(&fake, |_| Ok(()))).unwrap_err();
+        let Error::Timeout(timeout) = error else {
+            panic!("expected a timeout, got {}", error);
+        };
+        assert_eq!(timeout.phase, WaitPhase::Instances);
+        assert_eq!(timeout.pending.len(), 2);
     }
 }
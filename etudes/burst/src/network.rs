@@ -0,0 +1,225 @@
+use crate::Error;
+use crate::provider::IngressRule;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, TcpStream, ToSocketAddrs};
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+///The address range of the default VPC, which the machines are in unless the run has a VPC of
+///its own.
+pub(crate) const DEFAULT_VPC_CIDR: &str = "172.31.0.0/16";
+
+///Where the traffic an [`Ingress`] rule lets in may come from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    ///any address
+    Anywhere,
+    ///the given range in CIDR notation, such as `203.0.113.0/24`
+    Cidr(String),
+    ///the public ipv4 address the machine running the burst reaches the internet with
+    ThisMachine,
+    ///the other machines of the burst, that is the address range of their VPC
+    Cluster,
+}
+
+///An inbound rule of the security group the machines of a burst are placed in.
+///```rust
+/// # use burst::{Ingress, Source};
+/// let ssh = Ingress::tcp(22..=22, Source::ThisMachine);
+/// let metrics = Ingress::udp(8125..=8125, Source::Cluster);
+///```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ingress {
+    ///the ip protocol, `tcp` or `udp`
+    pub protocol: String,
+    ///the ports let in
+    pub ports: RangeInclusive<u16>,
+    ///where the traffic may come from
+    pub source: Source,
+}
+
+impl Ingress {
+    ///Let in tcp traffic to the given ports.
+    pub fn tcp(ports: RangeInclusive<u16>, source: Source) -> Self {
+        Self {
+            protocol: "tcp".to_string(),
+            ports,
+            source,
+        }
+    }
+
+    ///Let in udp traffic to the given ports.
+    pub fn udp(ports: RangeInclusive<u16>, source: Source) -> Self {
+        Self {
+            protocol: "udp".to_string(),
+            ports,
+            source,
+        }
+    }
+
+    ///The rules of a run which does not set its own: ssh from anywhere, and any tcp traffic
+    ///between the machines.
+    pub(crate) fn defaults() -> Vec<Self> {
+        vec![
+            Self::tcp(22..=22, Source::Anywhere),
+            Self::tcp(0..=65535, Source::Cluster),
+        ]
+    }
+
+    ///The rule as the provider takes it, with the source resolved to a range.
+    pub(crate) fn resolve(
+        &self,
+        cluster_cidr: &str,
+        this_machine: Option<Ipv4Addr>,
+    ) -> IngressRule {
+        let cidr = match &self.source {
+            Source::Anywhere => "0.0.0.0/0".to_string(),
+            Source::Cidr(cidr) => cidr.clone(),
+            Source::ThisMachine => format!(
+                "{}/32",
+                this_machine.expect("the public ip is looked up for the rules which need it")
+            ),
+            Source::Cluster => cluster_cidr.to_string(),
+        };
+        IngressRule {
+            protocol: self.protocol.clone(),
+            from_port: *self.ports.start() as i64,
+            to_port: *self.ports.end() as i64,
+            cidr,
+        }
+    }
+}
+
+///How the instances of a run are spread over the hardware of the cloud, as an EC2 placement
+///group created for the run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placement {
+    ///close together, for the lowest latency between the machines
+    Cluster,
+    ///each on distinct hardware
+    Spread,
+    ///in groups which do not share hardware with each other
+    Partition,
+}
+
+impl Placement {
+    pub(crate) fn strategy(self) -> &'static str {
+        match self {
+            Placement::Cluster => "cluster",
+            Placement::Spread => "spread",
+            Placement::Partition => "partition",
+        }
+    }
+}
+
+///The public ipv4 address of this machine, as seen by the AWS check service.
+pub(crate) fn public_ip() -> Result<Ipv4Addr, Error> {
+    check_ip("checkip.amazonaws.com:80", Duration::from_secs(10))
+        .map_err(|e| Error::io("look up the public ip of this machine", e))
+}
+
+//Ask the check service at the given address for the ipv4 address it sees, giving up on each
+//step which takes longer than `timeout`.
+fn check_ip(address: &str, timeout: Duration) -> io::Result<Ipv4Addr> {
+    let mut last_error = None;
+    let mut stream = None;
+    for addr in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(connected) => {
+                stream = Some(connected);
+                break;
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+    let mut stream = stream.ok_or_else(|| {
+        last_error.unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address found"))
+    })?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    let host = address.rsplit_once(':').map_or(address, |(host, _)| host);
+    write!(stream, "GET / HTTP/1.0\r\nHost: {}\r\n\r\n", host)?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let (_, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "the response has no body"))?;
+    // the security group rules only take ipv4 ranges
+    body.trim()
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sources_resolve_to_ranges() {
+        let this_machine = Some(Ipv4Addr::new(198, 51, 100, 7));
+        let cidrs: Vec<String> = [
+            Ingress::tcp(22..=22, Source::ThisMachine),
+            Ingress::udp(5000..=5100, Source::Cluster),
+            Ingress::tcp(80..=80, Source::Anywhere),
+            Ingress::tcp(443..=443, Source::Cidr("203.0.113.0/24".to_string())),
+        ]
+        .iter()
+        .map(|rule| rule.resolve("10.0.0.0/16", this_machine).cidr)
+        .collect();
+        assert_eq!(
+            cidrs,
+            [
+                "198.51.100.7/32",
+                "10.0.0.0/16",
+                "0.0.0.0/0",
+                "203.0.113.0/24"
+            ]
+        );
+
+        let rule = Ingress::udp(5000..=5100, Source::Cluster).resolve("10.0.0.0/16", None);
+        assert_eq!(
+            (rule.protocol.as_str(), rule.from_port, rule.to_port),
+            ("udp", 5000, 5100)
+        );
+    }
+
+    //A check service which answers the first request with the given body, or never if `None`.
+    fn check_service(body: Option<&'static str>) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let Some(body) = body else {
+                // keep the connection open without answering
+                std::thread::sleep(Duration::from_secs(5));
+                return;
+            };
+            let mut head = [0; 1024];
+            let _ = stream.read(&mut head).unwrap();
+            write!(stream, "HTTP/1.0 200 OK\r\n\r\n{}\n", body).unwrap();
+        });
+        address
+    }
+
+    #[test]
+    fn public_ip_is_an_ipv4_address() {
+        let timeout = Duration::from_millis(500);
+        assert_eq!(
+            check_ip(&check_service(Some("198.51.100.7")), timeout).unwrap(),
+            Ipv4Addr::new(198, 51, 100, 7)
+        );
+        let error = check_ip(&check_service(Some("2001:db8::7")), timeout).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn public_ip_lookup_gives_up_on_a_silent_service() {
+        let started = std::time::Instant::now();
+        let error = check_ip(&check_service(None), Duration::from_millis(200)).unwrap_err();
+        assert!(matches!(
+            error.kind(),
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+        ));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,88 @@
+use crate::Error;
+use std::collections::{BTreeSet, HashMap};
+
+///Order the setups into phases: every setup comes in a later phase than the setups it depends
+///on, and the setups of a phase run at the same time. Names are sorted within a phase.
+pub(crate) fn phases(
+    dependencies: &HashMap<String, Vec<String>>,
+) -> Result<Vec<Vec<String>>, Error> {
+    for (name, after) in dependencies {
+        if let Some(unknown) = after
+            .iter()
+            .find(|dependency| !dependencies.contains_key(*dependency))
+        {
+            return Err(Error::Dependencies(format!(
+                "{} depends on {}, which is not a setup",
+                name, unknown
+            )));
+        }
+    }
+
+    let mut left: BTreeSet<&String> = dependencies.keys().collect();
+    let mut phases: Vec<Vec<String>> = Vec::new();
+    while !left.is_empty() {
+        let ready: Vec<&String> = left
+            .iter()
+            .copied()
+            .filter(|name| {
+                dependencies[*name]
+                    .iter()
+                    .all(|dependency| !left.contains(dependency))
+            })
+            .collect();
+        if ready.is_empty() {
+            let cycle: Vec<&str> = left.iter().map(|name| name.as_str()).collect();
+            return Err(Error::Dependencies(format!(
+                "the setups {} depend on each other",
+                cycle.join(", ")
+            )));
+        }
+        for name in &ready {
+            left.remove(*name);
+        }
+        phases.push(ready.into_iter().cloned().collect());
+    }
+    Ok(phases)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dependencies(pairs: &[(&str, &[&str])]) -> HashMap<String, Vec<String>> {
+        pairs
+            .iter()
+            .map(|(name, after)| {
+                let after = after.iter().map(|name| name.to_string()).collect();
+                (name.to_string(), after)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn setups_run_after_their_dependencies() {
+        let phases = phases(&dependencies(&[
+            ("client", &["server", "database"]),
+            ("server", &["database"]),
+            ("database", &[]),
+            ("monitor", &[]),
+        ]))
+        .unwrap();
+        assert_eq!(
+            phases,
+            [vec!["database", "monitor"], vec!["server"], vec!["client"]]
+        );
+    }
+
+    #[test]
+    fn cycles_and_unknown_setups_are_refused() {
+        let cycle = phases(&dependencies(&[("a", &["b"]), ("b", &["a"]), ("c", &[])]));
+        assert_eq!(
+            cycle.unwrap_err().to_string(),
+            "the setups can not be ordered: the setups a, b depend on each other"
+        );
+
+        let unknown = phases(&dependencies(&[("client", &["server"])]));
+        assert!(matches!(unknown, Err(Error::Dependencies(_))));
+    }
+}
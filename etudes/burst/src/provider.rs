@@ -0,0 +1,399 @@
+//!The cloud provider abstraction used by [`BurstBuilder`](crate::BurstBuilder).
+//!
+//!Everything `run` needs from a cloud (networks, security groups, key pairs, spot requests and
+//!instances) goes through the [`Provider`] trait. [`Ec2`] talks to AWS, [`Fake`] keeps everything in
+//!memory so the whole lifecycle can be exercised without an AWS account, and [`Local`] hands out
+//!machines the developer already has.
+//!
+//!Every resource `run` creates belongs to a run id, which starts with the unix time the run
+//!started at. Key pairs and placement groups carry it in their names, everything else under the
+//![`RUN_TAG`] tag, so that [`reap`](crate::reap) can find what a run which died left behind.
+
+use crate::Error;
+use crate::ssh::{ConnectOptions, Session};
+use rand::Rng;
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Instant;
+
+mod ec2;
+mod fake;
+mod local;
+mod retry;
+
+pub use ec2::Ec2;
+pub use fake::Fake;
+pub use local::{Endpoint, Local};
+pub use retry::{RetryPolicy, Retrying, is_transient};
+
+///The tag holding the id of the run which created a resource.
+pub const RUN_TAG: &str = "burst:run";
+
+pub(crate) const SECURITY_GROUP_PREFIX: &str = "burst_sg_";
+pub(crate) const KEY_PAIR_PREFIX: &str = "burst_";
+pub(crate) const PLACEMENT_GROUP_PREFIX: &str = "burst_pg_";
+
+///An inbound rule of a security group.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IngressRule {
+    ///the ip protocol such as `tcp` or `udp`
+    pub protocol: String,
+    ///the first port of the range
+    pub from_port: i64,
+    ///the last port of the range
+    pub to_port: i64,
+    ///the source range in CIDR notation
+    pub cidr: String,
+}
+
+///A freshly created key pair, including its private key.
+#[derive(Debug, Clone)]
+pub struct KeyPair {
+    ///the fingerprint of the key, if the provider reports one
+    pub fingerprint: Option<String>,
+    ///the private key in PEM format
+    pub material: String,
+}
+
+///What to launch for a set of machines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LaunchSpec {
+    ///the machine image to boot
+    pub ami: String,
+    ///the instance type such as `t2.micro`
+    pub instance_type: String,
+    ///the security group the instances are placed in
+    pub security_group_id: String,
+    ///the key pair installed for ssh access
+    pub key_name: String,
+    ///the subnet to launch into, the default subnet of the default VPC if `None`
+    pub subnet_id: Option<String>,
+    ///the placement group to launch into, if any
+    pub placement_group: Option<String>,
+    ///a token unique to this launch, which makes it idempotent: launching the same spec again
+    ///must not launch more machines, because [`Retrying`] makes the call again when its response
+    ///was lost to a network failure
+    pub client_token: String,
+}
+
+///The status of a spot instance request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpotRequest {
+    ///the spot instance request id
+    pub id: String,
+    ///the request state: `open`, `active`, `closed`, `cancelled` or `failed`
+    pub state: String,
+    ///the status code explaining the state, such as `capacity-not-available`
+    pub status: Option<String>,
+    ///the instance fulfilling the request, once there is one
+    pub instance_id: Option<String>,
+}
+
+impl SpotRequest {
+    ///Whether the request is still open only because the spot market can not fulfil it now.
+    pub fn lacks_capacity(&self) -> bool {
+        self.state == "open"
+            && matches!(
+                self.status.as_deref(),
+                Some("capacity-not-available" | "capacity-oversubscribed" | "price-too-low")
+            )
+    }
+}
+
+///The description of an instance. Addresses are `None` until the instance is up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instance {
+    ///the instance id
+    pub instance_id: String,
+    ///the instance type
+    pub instance_type: Option<String>,
+    ///the public host name
+    pub public_dns: Option<String>,
+    ///the private ip address
+    pub private_ip: Option<String>,
+    ///the public ip address
+    pub public_ip: Option<String>,
+    ///the availability zone, if the provider has any
+    pub availability_zone: Option<String>,
+}
+
+///A resource created by a run, as listed by [`Provider::list_run_resources`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunResource {
+    ///the id of the run which created the resource
+    pub run_id: String,
+    ///what the resource is
+    pub kind: ResourceKind,
+    ///the id of the resource, or its name for key pairs and placement groups
+    pub id: String,
+}
+
+///The kinds of resources a run creates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResourceKind {
+    ///an instance which is not terminated
+    Instance,
+    ///a spot request which is still `open` or `active`
+    SpotRequest,
+    ///a security group
+    SecurityGroup,
+    ///a key pair
+    KeyPair,
+    ///a placement group
+    PlacementGroup,
+    ///a subnet
+    Subnet,
+    ///an internet gateway
+    InternetGateway {
+        ///the VPC the gateway is attached to, if it still is
+        vpc_id: Option<String>,
+    },
+    ///a VPC
+    Vpc,
+}
+
+///The operations `BurstBuilder::run` performs against a cloud.
+///
+///Every method is a single API call, or a few which only make sense together; retrying and waiting
+///is up to the caller, and `run` retries the transient failures through [`Retrying`]. The
+///networking methods are only called for runs which ask for a dedicated VPC or a placement group,
+///and fail by default.
+pub trait Provider: Sync {
+    ///Create a security group, in the given VPC or else in the default one, and return its id.
+    fn create_security_group(
+        &self,
+        name: &str,
+        description: &str,
+        vpc_id: Option<&str>,
+    ) -> impl Future<Output = Result<String, Error>> + Send;
+
+    ///Add inbound rules to a security group.
+    fn authorize_ingress(
+        &self,
+        group_id: &str,
+        rules: &[IngressRule],
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    ///Delete a security group.
+    fn delete_security_group(
+        &self,
+        group_id: &str,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    ///Create a key pair with the given name.
+    fn create_key_pair(&self, name: &str) -> impl Future<Output = Result<KeyPair, Error>> + Send;
+
+    ///Delete a key pair.
+    fn delete_key_pair(&self, name: &str) -> impl Future<Output = Result<(), Error>> + Send;
+
+    ///Request `count` spot instances and return the spot instance request ids. Without a
+    ///maximum hourly price the on-demand price is the limit.
+    fn request_spot_instances(
+        &self,
+        spec: &LaunchSpec,
+        count: i64,
+        max_price: Option<f64>,
+    ) -> impl Future<Output = Result<Vec<String>, Error>> + Send;
+
+    ///Launch `count` on-demand instances and return their instance ids. The instances must
+    ///terminate, rather than stop, when they are shut down from the inside.
+    fn run_instances(
+        &self,
+        spec: &LaunchSpec,
+        count: i64,
+    ) -> impl Future<Output = Result<Vec<String>, Error>> + Send;
+
+    ///Describe the given spot instance requests.
+    fn describe_spot_instance_requests(
+        &self,
+        ids: &[String],
+    ) -> impl Future<Output = Result<Vec<SpotRequest>, Error>> + Send;
+
+    ///Cancel the given spot instance requests. Instances already launched keep running.
+    fn cancel_spot_instance_requests(
+        &self,
+        ids: &[String],
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    ///Describe the given instances.
+    fn describe_instances(
+        &self,
+        ids: &[String],
+    ) -> impl Future<Output = Result<Vec<Instance>, Error>> + Send;
+
+    ///Terminate the given instances.
+    fn terminate_instances(&self, ids: &[String])
+    -> impl Future<Output = Result<(), Error>> + Send;
+
+    ///Create a VPC with the given address range and return its id.
+    fn create_vpc(&self, _cidr: &str) -> impl Future<Output = Result<String, Error>> + Send {
+        async { Err(unsupported("create the vpc")) }
+    }
+
+    ///Delete a VPC.
+    fn delete_vpc(&self, _vpc_id: &str) -> impl Future<Output = Result<(), Error>> + Send {
+        async { Err(unsupported("delete the vpc")) }
+    }
+
+    ///Create an internet gateway and return its id.
+    fn create_internet_gateway(&self) -> impl Future<Output = Result<String, Error>> + Send {
+        async { Err(unsupported("create the internet gateway")) }
+    }
+
+    ///Attach an internet gateway to the VPC.
+    fn attach_internet_gateway(
+        &self,
+        _gateway_id: &str,
+        _vpc_id: &str,
+    ) -> impl Future<Output = Result<(), Error>> + Send {
+        async { Err(unsupported("attach the internet gateway")) }
+    }
+
+    ///Route the traffic of the VPC to the internet through the internet gateway attached to it.
+    fn route_to_internet_gateway(
+        &self,
+        _vpc_id: &str,
+        _gateway_id: &str,
+    ) -> impl Future<Output = Result<(), Error>> + Send {
+        async { Err(unsupported("route the vpc to the internet gateway")) }
+    }
+
+    ///Detach an internet gateway from its VPC, if it is attached to one, and delete it.
+    fn delete_internet_gateway(
+        &self,
+        _gateway_id: &str,
+        _vpc_id: Option<&str>,
+    ) -> impl Future<Output = Result<(), Error>> + Send {
+        async { Err(unsupported("delete the internet gateway")) }
+    }
+
+    ///Create a subnet of the VPC whose instances get a public ip, and return its id.
+    fn create_subnet(
+        &self,
+        _vpc_id: &str,
+        _cidr: &str,
+    ) -> impl Future<Output = Result<String, Error>> + Send {
+        async { Err(unsupported("create the subnet")) }
+    }
+
+    ///Delete a subnet.
+    fn delete_subnet(&self, _subnet_id: &str) -> impl Future<Output = Result<(), Error>> + Send {
+        async { Err(unsupported("delete the subnet")) }
+    }
+
+    ///Create a placement group with the given strategy: `cluster`, `spread` or `partition`.
+    fn create_placement_group(
+        &self,
+        _name: &str,
+        _strategy: &str,
+    ) -> impl Future<Output = Result<(), Error>> + Send {
+        async { Err(unsupported("create the placement group")) }
+    }
+
+    ///Delete a placement group.
+    fn delete_placement_group(
+        &self,
+        _name: &str,
+    ) -> impl Future<Output = Result<(), Error>> + Send {
+        async { Err(unsupported("delete the placement group")) }
+    }
+
+    ///Get the console output of an instance, `None` while the instance has not written any.
+    ///Providers without a console fail, which makes the
+    ///[`ConsoleOutput`](crate::HostKeyPolicy::ConsoleOutput) host key policy unusable with them.
+    fn console_output(
+        &self,
+        _instance_id: &str,
+    ) -> impl Future<Output = Result<Option<String>, Error>> + Send {
+        async { Err(unsupported("get the console output")) }
+    }
+
+    ///Tag resources with the id of the run which created them, under the [`RUN_TAG`] key.
+    ///Providers without tags do nothing.
+    fn tag_run(
+        &self,
+        _ids: &[String],
+        _run_id: &str,
+    ) -> impl Future<Output = Result<(), Error>> + Send {
+        async { Ok(()) }
+    }
+
+    ///List the resources of every run, still going or not: the resources tagged with a run id,
+    ///the security groups named `burst_sg_<run id>`, the key pairs named `burst_<run id>` and
+    ///the placement groups named `burst_pg_<run id>`. Instances which are terminated and spot
+    ///requests which are closed are left out.
+    fn list_run_resources(&self) -> impl Future<Output = Result<Vec<RunResource>, Error>> + Send {
+        async { Err(unsupported("list the resources of the runs")) }
+    }
+
+    ///The current hourly spot price in USD of each of the given instance types, the highest over
+    ///the availability zones, for estimating what a burst costs. Instance types the spot market
+    ///does not sell are left out.
+    fn spot_prices(
+        &self,
+        _instance_types: &[String],
+    ) -> impl Future<Output = Result<HashMap<String, f64>, Error>> + Send {
+        async { Err(unsupported("get the spot prices")) }
+    }
+
+    ///Open a session to a launched instance. By default this connects over ssh to the public ip
+    ///as the options of the machine setup say, using the private key of the key pair created for
+    ///the run.
+    fn connect(
+        &self,
+        _instance_id: &str,
+        public_ip: &str,
+        private_key: &Path,
+        options: &ConnectOptions,
+    ) -> Result<Session, Error> {
+        let ip = IpAddr::from_str(public_ip).map_err(|e| Error::Connect {
+            address: public_ip.to_string(),
+            source: e.into(),
+        })?;
+        Session::connect_with(ip, private_key, options)
+    }
+
+    ///Make an instance terminate itself at the deadline, as a safety net in case the controlling
+    ///process dies before the teardown. By default this schedules a `shutdown` over the session,
+    ///again after every reboot, which terminates spot instances and on-demand instances launched
+    ///by `run_instances`; see [`Session::shut_down_at`].
+    fn schedule_shutdown(
+        &self,
+        _instance_id: &str,
+        session: &Session,
+        deadline: Instant,
+    ) -> Result<(), Error> {
+        session.shut_down_at(deadline, |session, minutes| {
+            session.cmd(&format!("sudo shutdown -h +{}", minutes))?;
+            Ok(())
+        })
+    }
+
+    ///A copy of this provider for the calls made from a runtime of their own while the caller's
+    ///runtime may be blocked, as the watchdog's and those of a teardown which was dropped are;
+    ///`None` when this provider can be used from any runtime. The connections an http client
+    ///pools are driven by the runtime which opened them, so a provider which pools them returns
+    ///a copy with a fresh client.
+    fn detached(&self) -> Result<Option<Self>, Error>
+    where
+        Self: Sized,
+    {
+        Ok(None)
+    }
+}
+
+///A fresh [`LaunchSpec::client_token`].
+pub(crate) fn client_token() -> String {
+    rand::rng()
+        .sample_iter(rand::distr::Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+fn unsupported(action: &str) -> Error {
+    Error::provider(action, "not supported by this provider")
+}
@@ -0,0 +1,899 @@
+use super::{
+    IngressRule, Instance, KEY_PAIR_PREFIX, KeyPair, LaunchSpec, PLACEMENT_GROUP_PREFIX, Provider,
+    RUN_TAG, ResourceKind, RunResource, SECURITY_GROUP_PREFIX, SpotRequest,
+};
+use crate::Error;
+use rusoto_ec2::Ec2 as _;
+use rusoto_ec2::{Filter, Tag};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+///The AWS EC2 provider.
+pub struct Ec2 {
+    client: rusoto_ec2::Ec2Client,
+    //builds a client which shares no connection with `client`, for `detached`
+    fresh: Option<Arc<FreshClient>>,
+}
+
+type FreshClient = dyn Fn() -> Result<rusoto_ec2::Ec2Client, Error> + Send + Sync;
+
+impl Ec2 {
+    ///Creates a client for the given region backed by the default tokio event loop.
+    ///The client will use the default credentials provider and tls client.
+    pub fn new(region: rusoto_core::Region) -> Self {
+        let fresh_region = region.clone();
+        Self {
+            client: rusoto_ec2::Ec2Client::new(region),
+            fresh: Some(Arc::new(move || {
+                let credentials = rusoto_credential::DefaultCredentialsProvider::new()
+                    .map_err(|e| Error::provider("load the default aws credentials", e))?;
+                let dispatcher = http_client()?;
+                Ok(rusoto_ec2::Ec2Client::new_with(
+                    dispatcher,
+                    credentials,
+                    fresh_region.clone(),
+                ))
+            })),
+        }
+    }
+
+    ///Creates a client for the given region which authenticates with the given credentials
+    ///provider, such as a `ChainProvider` or a `StaticProvider` for a local emulator.
+    pub fn with_credentials<P>(region: rusoto_core::Region, credentials: P) -> Result<Self, Error>
+    where
+        P: rusoto_credential::ProvideAwsCredentials + Send + Sync + 'static,
+    {
+        let credentials = Arc::new(credentials);
+        let fresh = move || {
+            let dispatcher = http_client()?;
+            Ok(rusoto_ec2::Ec2Client::new_with(
+                dispatcher,
+                credentials.clone(),
+                region.clone(),
+            ))
+        };
+        Ok(Self {
+            client: fresh()?,
+            fresh: Some(Arc::new(fresh)),
+        })
+    }
+
+    ///Creates a client for the given region which uses the credentials of a named profile from
+    ///the shared credentials file, `~/.aws/credentials` by default.
+    pub fn with_profile(region: rusoto_core::Region, profile: &str) -> Result<Self, Error> {
+        let credentials = rusoto_credential::ProfileProvider::with_default_credentials(profile)
+            .map_err(|e| Error::provider(format!("load the aws profile `{}`", profile), e))?;
+        Self::with_credentials(region, credentials)
+    }
+
+    ///Creates a client for the given region on top of an already configured rusoto client.
+    ///
+    ///The client can not be built again, so the watchdog and the teardown of a dropped burst
+    ///share its connections, which are driven by the runtime that opened them: run the burst on a
+    ///multi-thread runtime, or the calls they make may never complete. See
+    ///[`Provider::detached`].
+    pub fn with_client(client: rusoto_core::Client, region: rusoto_core::Region) -> Self {
+        Self {
+            client: rusoto_ec2::Ec2Client::new_with_client(client, region),
+            fresh: None,
+        }
+    }
+}
+
+fn http_client() -> Result<rusoto_core::HttpClient, Error> {
+    rusoto_core::HttpClient::new().map_err(|e| Error::provider("create the tls http client", e))
+}
+
+impl Provider for Ec2 {
+    async fn create_security_group(
+        &self,
+        name: &str,
+        description: &str,
+        vpc_id: Option<&str>,
+    ) -> Result<String, Error> {
+        let req = rusoto_ec2::CreateSecurityGroupRequest {
+            group_name: name.to_string(),
+            description: description.to_string(),
+            vpc_id: vpc_id.map(str::to_string),
+            ..Default::default()
+        };
+        Ok(self
+            .client
+            .create_security_group(req)
+            .await
+            .map_err(|e| Error::provider("create the security group", e))?
+            .group_id
+            .expect("aws creates security group always with an id"))
+    }
+
+    async fn authorize_ingress(&self, group_id: &str, rules: &[IngressRule]) -> Result<(), Error> {
+        let permissions = rules
+            .iter()
+            .map(|rule| rusoto_ec2::IpPermission {
+                from_port: Some(rule.from_port),
+                to_port: Some(rule.to_port),
+                ip_protocol: Some(rule.protocol.clone()),
+                ip_ranges: Some(vec![rusoto_ec2::IpRange {
+                    cidr_ip: Some(rule.cidr.clone()),
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            })
+            .collect();
+        let req = rusoto_ec2::AuthorizeSecurityGroupIngressRequest {
+            group_id: Some(group_id.to_string()),
+            ip_permissions: Some(permissions),
+            ..Default::default()
+        };
+        self.client
+            .authorize_security_group_ingress(req)
+            .await
+            .map_err(|e| Error::provider("authorize the security group ingress", e))?;
+        Ok(())
+    }
+
+    async fn delete_security_group(&self, group_id: &str) -> Result<(), Error> {
+        let req = rusoto_ec2::DeleteSecurityGroupRequest {
+            group_id: Some(group_id.to_string()),
+            ..Default::default()
+        };
+        self.client
+            .delete_security_group(req)
+            .await
+            .map_err(|e| Error::provider("delete the security group", e))?;
+        Ok(())
+    }
+
+    async fn create_key_pair(&self, name: &str) -> Result<KeyPair, Error> {
+        let req = rusoto_ec2::CreateKeyPairRequest {
+            key_name: name.to_string(),
+            ..Default::default()
+        };
+        let key_pair = self
+            .client
+            .create_key_pair(req)
+            .await
+            .map_err(|e| Error::provider("create the key-pair", e))?;
+        Ok(KeyPair {
+            fingerprint: key_pair.key_fingerprint,
+            material: key_pair
+                .key_material
+                .expect("the aws key-pair creation was successfull, so this should have value."),
+        })
+    }
+
+    async fn delete_key_pair(&self, name: &str) -> Result<(), Error> {
+        let req = rusoto_ec2::DeleteKeyPairRequest {
+            key_name: Some(name.to_string()),
+            ..Default::default()
+        };
+        self.client
+            .delete_key_pair(req)
+            .await
+            .map_err(|e| Error::provider("delete the key-pair", e))?;
+        Ok(())
+    }
+
+    async fn request_spot_instances(
+        &self,
+        spec: &LaunchSpec,
+        count: i64,
+        max_price: Option<f64>,
+    ) -> Result<Vec<String>, Error> {
+        let launch = rusoto_ec2::RequestSpotLaunchSpecification {
+            image_id: Some(spec.ami.clone()),
+            instance_type: Some(spec.instance_type.clone()),
+            security_group_ids: Some(vec![spec.security_group_id.clone()]),
+            key_name: Some(spec.key_name.clone()),
+            subnet_id: spec.subnet_id.clone(),
+            placement: spec
+                .placement_group
+                .as_ref()
+                .map(|group| rusoto_ec2::SpotPlacement {
+                    group_name: Some(group.clone()),
+                    ..Default::default()
+                }),
+            ..Default::default()
+        };
+
+        let req = rusoto_ec2::RequestSpotInstancesRequest {
+            launch_specification: Some(launch),
+            spot_price: max_price.map(|price| price.to_string()),
+            instance_count: Some(count),
+            client_token: Some(spec.client_token.clone()),
+            //instance_interruption_behavior: Some("stop".to_string()),
+            ..Default::default()
+        };
+        let requests = self
+            .client
+            .request_spot_instances(req)
+            .await
+            .map_err(|e| Error::provider("request spot instances", e))?
+            .spot_instance_requests
+            .expect(
+                "request spot instances should always return one or more spot instance requests",
+            );
+        Ok(requests
+            .into_iter()
+            .filter_map(|it| it.spot_instance_request_id)
+            .collect())
+    }
+
+    async fn run_instances(&self, spec: &LaunchSpec, count: i64) -> Result<Vec<String>, Error> {
+        let req = rusoto_ec2::RunInstancesRequest {
+            image_id: Some(spec.ami.clone()),
+            instance_type: Some(spec.instance_type.clone()),
+            security_group_ids: Some(vec![spec.security_group_id.clone()]),
+            key_name: Some(spec.key_name.clone()),
+            min_count: count,
+            max_count: count,
+            instance_initiated_shutdown_behavior: Some("terminate".to_string()),
+            client_token: Some(spec.client_token.clone()),
+            subnet_id: spec.subnet_id.clone(),
+            placement: spec
+                .placement_group
+                .as_ref()
+                .map(|group| rusoto_ec2::Placement {
+                    group_name: Some(group.clone()),
+                    ..Default::default()
+                }),
+            ..Default::default()
+        };
+        Ok(self
+            .client
+            .run_instances(req)
+            .await
+            .map_err(|e| Error::provider("launch on-demand instances", e))?
+            .instances
+            .into_iter()
+            .flatten()
+            .filter_map(|instance| instance.instance_id)
+            .collect())
+    }
+
+    async fn describe_spot_instance_requests(
+        &self,
+        ids: &[String],
+    ) -> Result<Vec<SpotRequest>, Error> {
+        let req = rusoto_ec2::DescribeSpotInstanceRequestsRequest {
+            spot_instance_request_ids: Some(ids.to_vec()),
+            ..Default::default()
+        };
+        Ok(self
+            .client
+            .describe_spot_instance_requests(req)
+            .await
+            .map_err(|e| Error::provider("describe the spot instance requests", e))?
+            .spot_instance_requests
+            .into_iter()
+            .flatten()
+            .map(|r| SpotRequest {
+                id: r
+                    .spot_instance_request_id
+                    .expect("any spot instance request should have an id"),
+                state: r.state.unwrap_or_default(),
+                status: r.status.and_then(|status| status.code),
+                instance_id: r.instance_id,
+            })
+            .collect())
+    }
+
+    async fn cancel_spot_instance_requests(&self, ids: &[String]) -> Result<(), Error> {
+        let req = rusoto_ec2::CancelSpotInstanceRequestsRequest {
+            spot_instance_request_ids: ids.to_vec(),
+            ..Default::default()
+        };
+        self.client
+            .cancel_spot_instance_requests(req)
+            .await
+            .map_err(|e| Error::provider("cancel the spot instance requests", e))?;
+        Ok(())
+    }
+
+    async fn describe_instances(&self, ids: &[String]) -> Result<Vec<Instance>, Error> {
+        let req = rusoto_ec2::DescribeInstancesRequest {
+            instance_ids: Some(ids.to_vec()),
+            ..Default::default()
+        };
+        Ok(self
+            .client
+            .describe_instances(req)
+            .await
+            .map_err(|e| Error::provider("describe the instances", e))?
+            .reservations
+            .unwrap_or_default()
+            .into_iter()
+            .flat_map(|reservation| reservation.instances.unwrap_or_default())
+            .filter_map(|instance| {
+                Some(Instance {
+                    instance_id: instance.instance_id?,
+                    instance_type: instance.instance_type,
+                    public_dns: instance.public_dns_name,
+                    private_ip: instance.private_ip_address,
+                    public_ip: instance.public_ip_address,
+                    availability_zone: instance
+                        .placement
+                        .and_then(|placement| placement.availability_zone),
+                })
+            })
+            .collect())
+    }
+
+    async fn terminate_instances(&self, ids: &[String]) -> Result<(), Error> {
+        let req = rusoto_ec2::TerminateInstancesRequest {
+            instance_ids: ids.to_vec(),
+            ..Default::default()
+        };
+        self.client
+            .terminate_instances(req)
+            .await
+            .map_err(|e| Error::provider("terminate the instances", e))?;
+        Ok(())
+    }
+
+    async fn create_vpc(&self, cidr: &str) -> Result<String, Error> {
+        let req = rusoto_ec2::CreateVpcRequest {
+            cidr_block: cidr.to_string(),
+            ..Default::default()
+        };
+        Ok(self
+            .client
+            .create_vpc(req)
+            .await
+            .map_err(|e| Error::provider("create the vpc", e))?
+            .vpc
+            .and_then(|vpc| vpc.vpc_id)
+            .expect("aws creates the vpc always with an id"))
+    }
+
+    async fn delete_vpc(&self, vpc_id: &str) -> Result<(), Error> {
+        let req = rusoto_ec2::DeleteVpcRequest {
+            vpc_id: vpc_id.to_string(),
+            ..Default::default()
+        };
+        self.client
+            .delete_vpc(req)
+            .await
+            .map_err(|e| Error::provider("delete the vpc", e))?;
+        Ok(())
+    }
+
+    async fn create_internet_gateway(&self) -> Result<String, Error> {
+        Ok(self
+            .client
+            .create_internet_gateway(Default::default())
+            .await
+            .map_err(|e| Error::provider("create the internet gateway", e))?
+            .internet_gateway
+            .and_then(|gateway| gateway.internet_gateway_id)
+            .expect("aws creates the internet gateway always with an id"))
+    }
+
+    async fn attach_internet_gateway(&self, gateway_id: &str, vpc_id: &str) -> Result<(), Error> {
+        let req = rusoto_ec2::AttachInternetGatewayRequest {
+            internet_gateway_id: gateway_id.to_string(),
+            vpc_id: vpc_id.to_string(),
+            ..Default::default()
+        };
+        self.client
+            .attach_internet_gateway(req)
+            .await
+            .map_err(|e| Error::provider("attach the internet gateway", e))?;
+        Ok(())
+    }
+
+    async fn route_to_internet_gateway(&self, vpc_id: &str, gateway_id: &str) -> Result<(), Error> {
+        // the main route table comes with the vpc, and goes away with it
+        let req = rusoto_ec2::DescribeRouteTablesRequest {
+            filters: Some(vec![
+                rusoto_ec2::Filter {
+                    name: Some("vpc-id".to_string()),
+                    values: Some(vec![vpc_id.to_string()]),
+                },
+                rusoto_ec2::Filter {
+                    name: Some("association.main".to_string()),
+                    values: Some(vec!["true".to_string()]),
+                },
+            ]),
+            ..Default::default()
+        };
+        let route_table_id = self
+            .client
+            .describe_route_tables(req)
+            .await
+            .map_err(|e| Error::provider("describe the route tables", e))?
+            .route_tables
+            .unwrap_or_default()
+            .into_iter()
+            .find_map(|table| table.route_table_id)
+            .ok_or_else(|| {
+                Error::provider(
+                    "describe the route tables",
+                    "the vpc has no main route table",
+                )
+            })?;
+        let req = rusoto_ec2::CreateRouteRequest {
+            route_table_id,
+            destination_cidr_block: Some("0.0.0.0/0".to_string()),
+            gateway_id: Some(gateway_id.to_string()),
+            ..Default::default()
+        };
+        self.client
+            .create_route(req)
+            .await
+            .map_err(|e| Error::provider("route the vpc to the internet gateway", e))?;
+        Ok(())
+    }
+
+    async fn delete_internet_gateway(
+        &self,
+        gateway_id: &str,
+        vpc_id: Option<&str>,
+    ) -> Result<(), Error> {
+        if let Some(vpc_id) = vpc_id {
+            let req = rusoto_ec2::DetachInternetGatewayRequest {
+                internet_gateway_id: gateway_id.to_string(),
+                vpc_id: vpc_id.to_string(),
+                ..Default::default()
+            };
+            match self.client.detach_internet_gateway(req).await {
+                // detached by an earlier attempt whose deletion failed
+                Err(e) if e.to_string().contains("Gateway.NotAttached") => {}
+                result => {
+                    result.map_err(|e| Error::provider("detach the internet gateway", e))?;
+                }
+            }
+        }
+        let req = rusoto_ec2::DeleteInternetGatewayRequest {
+            internet_gateway_id: gateway_id.to_string(),
+            ..Default::default()
+        };
+        self.client
+            .delete_internet_gateway(req)
+            .await
+            .map_err(|e| Error::provider("delete the internet gateway", e))?;
+        Ok(())
+    }
+
+    async fn create_subnet(&self, vpc_id: &str, cidr: &str) -> Result<String, Error> {
+        let req = rusoto_ec2::CreateSubnetRequest {
+            vpc_id: vpc_id.to_string(),
+            cidr_block: cidr.to_string(),
+            ..Default::default()
+        };
+        let subnet_id = self
+            .client
+            .create_subnet(req)
+            .await
+            .map_err(|e| Error::provider("create the subnet", e))?
+            .subnet
+            .and_then(|subnet| subnet.subnet_id)
+            .expect("aws creates the subnet always with an id");
+
+        let req = rusoto_ec2::ModifySubnetAttributeRequest {
+            subnet_id: subnet_id.clone(),
+            map_public_ip_on_launch: Some(rusoto_ec2::AttributeBooleanValue { value: Some(true) }),
+            ..Default::default()
+        };
+        if let Err(e) = self.client.modify_subnet_attribute(req).await {
+            // the subnet is not known to the teardown yet
+            let req = rusoto_ec2::DeleteSubnetRequest {
+                subnet_id,
+                ..Default::default()
+            };
+            let _ = self.client.delete_subnet(req).await;
+            return Err(Error::provider("give the subnet public ips", e));
+        }
+        Ok(subnet_id)
+    }
+
+    async fn delete_subnet(&self, subnet_id: &str) -> Result<(), Error> {
+        let req = rusoto_ec2::DeleteSubnetRequest {
+            subnet_id: subnet_id.to_string(),
+            ..Default::default()
+        };
+        self.client
+            .delete_subnet(req)
+            .await
+            .map_err(|e| Error::provider("delete the subnet", e))?;
+        Ok(())
+    }
+
+    async fn create_placement_group(&self, name: &str, strategy: &str) -> Result<(), Error> {
+        let req = rusoto_ec2::CreatePlacementGroupRequest {
+            group_name: Some(name.to_string()),
+            strategy: Some(strategy.to_string()),
+            ..Default::default()
+        };
+        self.client
+            .create_placement_group(req)
+            .await
+            .map_err(|e| Error::provider("create the placement group", e))?;
+        Ok(())
+    }
+
+    async fn delete_placement_group(&self, name: &str) -> Result<(), Error> {
+        let req = rusoto_ec2::DeletePlacementGroupRequest {
+            group_name: name.to_string(),
+            ..Default::default()
+        };
+        self.client
+            .delete_placement_group(req)
+            .await
+            .map_err(|e| Error::provider("delete the placement group", e))?;
+        Ok(())
+    }
+
+    async fn console_output(&self, instance_id: &str) -> Result<Option<String>, Error> {
+        let req = rusoto_ec2::GetConsoleOutputRequest {
+            instance_id: instance_id.to_string(),
+            ..Default::default()
+        };
+        let output = self
+            .client
+            .get_console_output(req)
+            .await
+            .map_err(|e| Error::provider("get the console output", e))?
+            .output;
+        // the output is base64 encoded, and missing until the instance wrote some
+        output
+            .map(|output| {
+                base64::decode(output.trim())
+                    .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+                    .map_err(|e| Error::provider("decode the console output", e))
+            })
+            .transpose()
+    }
+
+    async fn tag_run(&self, ids: &[String], run_id: &str) -> Result<(), Error> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let req = rusoto_ec2::CreateTagsRequest {
+            resources: ids.to_vec(),
+            tags: vec![Tag {
+                key: Some(RUN_TAG.to_string()),
+                value: Some(run_id.to_string()),
+            }],
+            ..Default::default()
+        };
+        self.client
+            .create_tags(req)
+            .await
+            .map_err(|e| Error::provider("tag the resources", e))?;
+        Ok(())
+    }
+
+    async fn spot_prices(&self, instance_types: &[String]) -> Result<HashMap<String, f64>, Error> {
+        // the prices in effect at a single instant, one per instance type and availability zone
+        let now = iso8601(SystemTime::now());
+        let mut prices: HashMap<String, f64> = HashMap::new();
+        let mut next_token = None;
+        loop {
+            let req = rusoto_ec2::DescribeSpotPriceHistoryRequest {
+                instance_types: Some(instance_types.to_vec()),
+                product_descriptions: Some(vec!["Linux/UNIX".to_string()]),
+                start_time: Some(now.clone()),
+                end_time: Some(now.clone()),
+                next_token,
+                ..Default::default()
+            };
+            let res = self
+                .client
+                .describe_spot_price_history(req)
+                .await
+                .map_err(|e| Error::provider("describe the spot price history", e))?;
+            for spot_price in res.spot_price_history.unwrap_or_default() {
+                let price = spot_price.spot_price.and_then(|price| price.parse().ok());
+                if let (Some(instance_type), Some(price)) = (spot_price.instance_type, price) {
+                    let highest = prices.entry(instance_type).or_insert(price);
+                    *highest = highest.max(price);
+                }
+            }
+            next_token = res.next_token.filter(|token| !token.is_empty());
+            if next_token.is_none() {
+                return Ok(prices);
+            }
+        }
+    }
+
+    async fn list_run_resources(&self) -> Result<Vec<RunResource>, Error> {
+        let mut resources = Vec::new();
+
+        let mut next_token = None;
+        loop {
+            let req = rusoto_ec2::DescribeInstancesRequest {
+                filters: Some(vec![
+                    filter("tag-key", &[RUN_TAG]),
+                    filter(
+                        "instance-state-name",
+                        &["pending", "running", "stopping", "stopped"],
+                    ),
+                ]),
+                next_token,
+                ..Default::default()
+            };
+            let res = self
+                .client
+                .describe_instances(req)
+                .await
+                .map_err(|e| Error::provider("describe the instances", e))?;
+            let instances = res
+                .reservations
+                .unwrap_or_default()
+                .into_iter()
+                .flat_map(|reservation| reservation.instances.unwrap_or_default());
+            for instance in instances {
+                if let (Some(id), Some(run_id)) = (instance.instance_id, run_tag(instance.tags)) {
+                    resources.push(resource(run_id, ResourceKind::Instance, id));
+                }
+            }
+            next_token = res.next_token.filter(|token| !token.is_empty());
+            if next_token.is_none() {
+                break;
+            }
+        }
+
+        let mut next_token = None;
+        loop {
+            let req = rusoto_ec2::DescribeSpotInstanceRequestsRequest {
+                filters: Some(vec![
+                    filter("tag-key", &[RUN_TAG]),
+                    filter("state", &["open", "active"]),
+                ]),
+                next_token,
+                ..Default::default()
+            };
+            let res = self
+                .client
+                .describe_spot_instance_requests(req)
+                .await
+                .map_err(|e| Error::provider("describe the spot instance requests", e))?;
+            for request in res.spot_instance_requests.unwrap_or_default() {
+                if let (Some(id), Some(run_id)) =
+                    (request.spot_instance_request_id, run_tag(request.tags))
+                {
+                    resources.push(resource(run_id, ResourceKind::SpotRequest, id));
+                }
+            }
+            next_token = res.next_token.filter(|token| !token.is_empty());
+            if next_token.is_none() {
+                break;
+            }
+        }
+
+        let mut next_token = None;
+        loop {
+            let req = rusoto_ec2::DescribeSecurityGroupsRequest {
+                filters: Some(vec![filter(
+                    "group-name",
+                    &[&format!("{}*", SECURITY_GROUP_PREFIX)],
+                )]),
+                next_token,
+                ..Default::default()
+            };
+            let res = self
+                .client
+                .describe_security_groups(req)
+                .await
+                .map_err(|e| Error::provider("describe the security groups", e))?;
+            for group in res.security_groups.unwrap_or_default() {
+                let run_id = group
+                    .group_name
+                    .as_deref()
+                    .and_then(|name| name.strip_prefix(SECURITY_GROUP_PREFIX));
+                if let (Some(id), Some(run_id)) = (group.group_id.clone(), run_id) {
+                    resources.push(resource(
+                        run_id.to_string(),
+                        ResourceKind::SecurityGroup,
+                        id,
+                    ));
+                }
+            }
+            next_token = res.next_token.filter(|token| !token.is_empty());
+            if next_token.is_none() {
+                break;
+            }
+        }
+
+        // the key-pairs and the placement groups are listed whole, without pages
+        let req = rusoto_ec2::DescribeKeyPairsRequest {
+            filters: Some(vec![filter(
+                "key-name",
+                &[&format!("{}*", KEY_PAIR_PREFIX)],
+            )]),
+            ..Default::default()
+        };
+        let key_pairs = self
+            .client
+            .describe_key_pairs(req)
+            .await
+            .map_err(|e| Error::provider("describe the key-pairs", e))?
+            .key_pairs
+            .unwrap_or_default();
+        for name in key_pairs
+            .into_iter()
+            .filter_map(|key_pair| key_pair.key_name)
+        {
+            if let Some(run_id) = name.strip_prefix(KEY_PAIR_PREFIX) {
+                resources.push(resource(
+                    run_id.to_string(),
+                    ResourceKind::KeyPair,
+                    name.clone(),
+                ));
+            }
+        }
+
+        let req = rusoto_ec2::DescribePlacementGroupsRequest {
+            filters: Some(vec![filter(
+                "group-name",
+                &[&format!("{}*", PLACEMENT_GROUP_PREFIX)],
+            )]),
+            ..Default::default()
+        };
+        let placement_groups = self
+            .client
+            .describe_placement_groups(req)
+            .await
+            .map_err(|e| Error::provider("describe the placement groups", e))?
+            .placement_groups
+            .unwrap_or_default();
+        for name in placement_groups
+            .into_iter()
+            .filter_map(|group| group.group_name)
+        {
+            if let Some(run_id) = name.strip_prefix(PLACEMENT_GROUP_PREFIX) {
+                resources.push(resource(
+                    run_id.to_string(),
+                    ResourceKind::PlacementGroup,
+                    name.clone(),
+                ));
+            }
+        }
+
+        let mut next_token = None;
+        loop {
+            let req = rusoto_ec2::DescribeSubnetsRequest {
+                filters: Some(vec![filter("tag-key", &[RUN_TAG])]),
+                next_token,
+                ..Default::default()
+            };
+            let res = self
+                .client
+                .describe_subnets(req)
+                .await
+                .map_err(|e| Error::provider("describe the subnets", e))?;
+            for subnet in res.subnets.unwrap_or_default() {
+                if let (Some(id), Some(run_id)) = (subnet.subnet_id, run_tag(subnet.tags)) {
+                    resources.push(resource(run_id, ResourceKind::Subnet, id));
+                }
+            }
+            next_token = res.next_token.filter(|token| !token.is_empty());
+            if next_token.is_none() {
+                break;
+            }
+        }
+
+        let mut next_token = None;
+        loop {
+            let req = rusoto_ec2::DescribeInternetGatewaysRequest {
+                filters: Some(vec![filter("tag-key", &[RUN_TAG])]),
+                next_token,
+                ..Default::default()
+            };
+            let res = self
+                .client
+                .describe_internet_gateways(req)
+                .await
+                .map_err(|e| Error::provider("describe the internet gateways", e))?;
+            for gateway in res.internet_gateways.unwrap_or_default() {
+                let vpc_id = gateway
+                    .attachments
+                    .unwrap_or_default()
+                    .into_iter()
+                    .find_map(|attachment| attachment.vpc_id);
+                if let (Some(id), Some(run_id)) =
+                    (gateway.internet_gateway_id, run_tag(gateway.tags))
+                {
+                    resources.push(resource(
+                        run_id,
+                        ResourceKind::InternetGateway { vpc_id },
+                        id,
+                    ));
+                }
+            }
+            next_token = res.next_token.filter(|token| !token.is_empty());
+            if next_token.is_none() {
+                break;
+            }
+        }
+
+        let mut next_token = None;
+        loop {
+            let req = rusoto_ec2::DescribeVpcsRequest {
+                filters: Some(vec![filter("tag-key", &[RUN_TAG])]),
+                next_token,
+                ..Default::default()
+            };
+            let res = self
+                .client
+                .describe_vpcs(req)
+                .await
+                .map_err(|e| Error::provider("describe the vpcs", e))?;
+            for vpc in res.vpcs.unwrap_or_default() {
+                if let (Some(id), Some(run_id)) = (vpc.vpc_id, run_tag(vpc.tags)) {
+                    resources.push(resource(run_id, ResourceKind::Vpc, id));
+                }
+            }
+            next_token = res.next_token.filter(|token| !token.is_empty());
+            if next_token.is_none() {
+                break;
+            }
+        }
+
+        Ok(resources)
+    }
+
+    fn detached(&self) -> Result<Option<Self>, Error> {
+        self.fresh
+            .as_ref()
+            .map(|fresh| {
+                Ok(Self {
+                    client: fresh()?,
+                    fresh: Some(fresh.clone()),
+                })
+            })
+            .transpose()
+    }
+}
+
+//The given time as an ISO 8601 UTC timestamp, such as `2024-03-01T12:00:00Z`.
+fn iso8601(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let (days, secs) = (secs / 86_400, secs % 86_400);
+    // the civil date of a day count, shifted to start the years in march
+    let days = days as i64 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+fn filter(name: &str, values: &[&str]) -> Filter {
+    Filter {
+        name: Some(name.to_string()),
+        values: Some(values.iter().map(|value| value.to_string()).collect()),
+    }
+}
+
+fn run_tag(tags: Option<Vec<Tag>>) -> Option<String> {
+    tags.unwrap_or_default()
+        .into_iter()
+        .find(|tag| tag.key.as_deref() == Some(RUN_TAG))
+        .and_then(|tag| tag.value)
+}
+
+fn resource(run_id: String, kind: ResourceKind, id: String) -> RunResource {
+    RunResource { run_id, kind, id }
+}
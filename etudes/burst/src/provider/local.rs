@@ -0,0 +1,396 @@
+use super::{IngressRule, Instance, KeyPair, LaunchSpec, Provider, SpotRequest};
+use crate::Error;
+use crate::ssh::{ConnectOptions, Session};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Instant;
+
+///A machine the [`Local`] provider can hand out.
+#[derive(Debug, Clone)]
+pub enum Endpoint {
+    ///An ssh server which is already running, such as a developer box or a CI runner.
+    Ssh {
+        ///the address of the ssh server
+        addr: SocketAddr,
+        ///the user to log in as
+        username: String,
+        ///the private key used to authenticate
+        private_key: PathBuf,
+    },
+    ///A fresh temporary directory on this host in which commands run as subprocesses.
+    Sandbox,
+}
+
+///A provider which uses a fixed list of machines instead of launching cloud instances.
+///
+///Every requested machine takes the next free endpoint, regardless of the instance type or ami
+///of its setup, so `MachineSetup` closures and `Machine::run` work unchanged against local
+///hardware. Security groups and key pairs are not needed and only exist by name.
+///
+///```rust, no_run
+/// # use burst::{provider::Local, BurstBuilder, MachineSetup};
+/// # use tokio::runtime::Runtime;
+/// let mut builder = BurstBuilder::default();
+/// builder.add_setup(
+///     "server".to_string(),
+///     1,
+///     MachineSetup::new("t2.micro", "ami-083e865b97bdf1c1b", |ssh| {
+///         ssh.cmd("hostname")?;
+///         Ok(())
+///     }),
+/// );
+/// builder.add_setup(
+///     "client".to_string(),
+///     2,
+///     MachineSetup::new("t2.micro", "ami-083e865b97bdf1c1b", |ssh| {
+///         ssh.cmd("date")?;
+///         Ok(())
+///     }),
+/// );
+///
+/// let local = Local::new()
+///     .with_ssh("127.0.0.1:2222".parse().unwrap(), "dev", "/home/dev/.ssh/id_rsa")
+///     .with_sandboxes(2);
+/// let rt = Runtime::new().unwrap();
+/// rt.block_on(builder.run_with(&local, |vms| Ok(()))).unwrap();
+///```
+#[derive(Default)]
+pub struct Local {
+    endpoints: Vec<Endpoint>,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    next_id: usize,
+    // instance id to the index of the endpoint it occupies
+    instances: HashMap<String, usize>,
+    // spot request id to the instance launched for it, and whether the request was cancelled
+    requests: HashMap<String, (String, bool)>,
+    sandboxes: HashMap<String, tempfile::TempDir>,
+}
+
+impl State {
+    fn next_id(&mut self, prefix: &str) -> String {
+        self.next_id += 1;
+        format!("{}-local-{}", prefix, self.next_id)
+    }
+}
+
+impl Local {
+    ///Creates a provider without any endpoints.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///Add an ssh endpoint.
+    pub fn with_ssh<P: Into<PathBuf>>(
+        mut self,
+        addr: SocketAddr,
+        username: &str,
+        private_key: P,
+    ) -> Self {
+        self.endpoints.push(Endpoint::Ssh {
+            addr,
+            username: username.to_string(),
+            private_key: private_key.into(),
+        });
+        self
+    }
+
+    ///Add `count` subprocess sandboxes.
+    pub fn with_sandboxes(mut self, count: usize) -> Self {
+        self.endpoints
+            .extend(std::iter::repeat_n(Endpoint::Sandbox, count));
+        self
+    }
+
+    ///Take `count` free endpoints and return the instance ids they are known by.
+    fn allocate(&self, count: i64) -> Result<Vec<String>, Error> {
+        let mut state = self.state.lock().unwrap();
+        let free: Vec<usize> = (0..self.endpoints.len())
+            .filter(|index| !state.instances.values().any(|used| used == index))
+            .take(count as usize)
+            .collect();
+        if (free.len() as i64) < count {
+            return Err(Error::provider(
+                "allocate local machines",
+                format!(
+                    "not enough local machines: {} requested, {} available",
+                    count,
+                    free.len()
+                ),
+            ));
+        }
+
+        Ok(free
+            .into_iter()
+            .map(|index| {
+                let instance_id = state.next_id("i");
+                state.instances.insert(instance_id.clone(), index);
+                instance_id
+            })
+            .collect())
+    }
+
+    fn endpoint(&self, instance_id: &str) -> Result<&Endpoint, Error> {
+        let state = self.state.lock().unwrap();
+        match state.instances.get(instance_id) {
+            Some(&index) => Ok(&self.endpoints[index]),
+            None => Err(Error::provider(
+                "find the local machine",
+                format!(
+                    "InvalidInstanceID.NotFound: the instance ID '{}' does not exist",
+                    instance_id
+                ),
+            )),
+        }
+    }
+}
+
+impl Provider for Local {
+    async fn create_security_group(
+        &self,
+        _name: &str,
+        _description: &str,
+        _vpc_id: Option<&str>,
+    ) -> Result<String, Error> {
+        Ok(self.state.lock().unwrap().next_id("sg"))
+    }
+
+    async fn authorize_ingress(
+        &self,
+        _group_id: &str,
+        _rules: &[IngressRule],
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn delete_security_group(&self, _group_id: &str) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn create_key_pair(&self, _name: &str) -> Result<KeyPair, Error> {
+        Ok(KeyPair {
+            fingerprint: None,
+            material: String::new(),
+        })
+    }
+
+    async fn delete_key_pair(&self, _name: &str) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn request_spot_instances(
+        &self,
+        _spec: &LaunchSpec,
+        count: i64,
+        _max_price: Option<f64>,
+    ) -> Result<Vec<String>, Error> {
+        let instance_ids = self.allocate(count)?;
+        let mut state = self.state.lock().unwrap();
+        Ok(instance_ids
+            .into_iter()
+            .map(|instance_id| {
+                let request_id = state.next_id("sir");
+                state
+                    .requests
+                    .insert(request_id.clone(), (instance_id, false));
+                request_id
+            })
+            .collect())
+    }
+
+    async fn run_instances(&self, _spec: &LaunchSpec, count: i64) -> Result<Vec<String>, Error> {
+        self.allocate(count)
+    }
+
+    async fn describe_spot_instance_requests(
+        &self,
+        ids: &[String],
+    ) -> Result<Vec<SpotRequest>, Error> {
+        let state = self.state.lock().unwrap();
+        ids.iter()
+            .map(|id| match state.requests.get(id) {
+                // like ec2, a cancelled request keeps its instance running
+                Some((instance_id, true)) => Ok(SpotRequest {
+                    id: id.clone(),
+                    state: "cancelled".to_string(),
+                    status: Some("request-canceled-and-instance-running".to_string()),
+                    instance_id: Some(instance_id.clone()),
+                }),
+                Some((instance_id, false)) => Ok(SpotRequest {
+                    id: id.clone(),
+                    state: "active".to_string(),
+                    status: Some("fulfilled".to_string()),
+                    instance_id: Some(instance_id.clone()),
+                }),
+                None => Err(Error::provider(
+                    "describe the spot instance requests",
+                    format!("InvalidSpotInstanceRequestID.NotFound: {}", id),
+                )),
+            })
+            .collect()
+    }
+
+    async fn cancel_spot_instance_requests(&self, ids: &[String]) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        for id in ids {
+            if let Some((_, cancelled)) = state.requests.get_mut(id) {
+                *cancelled = true;
+            }
+        }
+        Ok(())
+    }
+
+    async fn describe_instances(&self, ids: &[String]) -> Result<Vec<Instance>, Error> {
+        ids.iter()
+            .map(|id| {
+                let ip = match self.endpoint(id)? {
+                    Endpoint::Ssh { addr, .. } => addr.ip().to_string(),
+                    Endpoint::Sandbox => "127.0.0.1".to_string(),
+                };
+                Ok(Instance {
+                    instance_id: id.clone(),
+                    instance_type: Some("local".to_string()),
+                    public_dns: Some(ip.clone()),
+                    private_ip: Some(ip.clone()),
+                    public_ip: Some(ip),
+                    availability_zone: None,
+                })
+            })
+            .collect()
+    }
+
+    async fn terminate_instances(&self, ids: &[String]) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        for id in ids {
+            state.instances.remove(id);
+            state.sandboxes.remove(id);
+        }
+        Ok(())
+    }
+
+    fn schedule_shutdown(
+        &self,
+        _instance_id: &str,
+        _session: &Session,
+        _deadline: Instant,
+    ) -> Result<(), Error> {
+        // these are machines the developer owns, they must never be shut down by a burst
+        Ok(())
+    }
+
+    fn connect(
+        &self,
+        instance_id: &str,
+        _public_ip: &str,
+        _private_key: &Path,
+        options: &ConnectOptions,
+    ) -> Result<Session, Error> {
+        match self.endpoint(instance_id)? {
+            Endpoint::Ssh {
+                addr,
+                username,
+                private_key,
+            } => {
+                // the endpoint knows how to log in, the setup only says how patient to be
+                let options = ConnectOptions {
+                    username: username.clone(),
+                    port: addr.port(),
+                    ..options.clone()
+                };
+                Session::connect_with(addr.ip(), private_key, &options)
+            }
+            Endpoint::Sandbox => {
+                let mut state = self.state.lock().unwrap();
+                if !state.sandboxes.contains_key(instance_id) {
+                    let dir = tempfile::Builder::new()
+                        .prefix("burst_sandbox_")
+                        .tempdir()
+                        .map_err(|e| Error::io("create a sandbox directory", e))?;
+                    state.sandboxes.insert(instance_id.to_string(), dir);
+                }
+                Ok(Session::subprocess(state.sandboxes[instance_id].path()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::runtime::Runtime;
+
+    fn spec() -> LaunchSpec {
+        LaunchSpec {
+            ami: "ami-local".to_string(),
+            instance_type: "t2.micro".to_string(),
+            security_group_id: "sg".to_string(),
+            key_name: "key".to_string(),
+            subnet_id: None,
+            placement_group: None,
+            client_token: "token".to_string(),
+        }
+    }
+
+    #[test]
+    fn endpoints_are_handed_out_once() {
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let local = Local::new().with_sandboxes(2);
+            let ids = local
+                .request_spot_instances(&spec(), 2, None)
+                .await
+                .unwrap();
+            assert!(
+                local
+                    .request_spot_instances(&spec(), 1, None)
+                    .await
+                    .is_err()
+            );
+
+            let requests = local.describe_spot_instance_requests(&ids).await.unwrap();
+            let instance_ids: Vec<String> =
+                requests.into_iter().filter_map(|r| r.instance_id).collect();
+            local.terminate_instances(&instance_ids[..1]).await.unwrap();
+            assert_eq!(
+                local
+                    .request_spot_instances(&spec(), 1, None)
+                    .await
+                    .unwrap()
+                    .len(),
+                1
+            );
+        });
+    }
+
+    #[test]
+    fn sandbox_runs_commands_in_its_own_directory() {
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let local = Local::new().with_sandboxes(2);
+            let ids = local
+                .request_spot_instances(&spec(), 2, None)
+                .await
+                .unwrap();
+            let requests = local.describe_spot_instance_requests(&ids).await.unwrap();
+            let first = requests[0].instance_id.clone().unwrap();
+            let second = requests[1].instance_id.clone().unwrap();
+
+            let key = Path::new("unused");
+            let ssh = local
+                .connect(&first, "127.0.0.1", key, &Default::default())
+                .unwrap();
+            ssh.cmd("echo hello > greeting").unwrap();
+            assert_eq!(ssh.cmd("cat greeting").unwrap(), "hello\n");
+
+            let other = local
+                .connect(&second, "127.0.0.1", key, &Default::default())
+                .unwrap();
+            assert_eq!(other.cmd("ls").unwrap(), "");
+        });
+    }
+}
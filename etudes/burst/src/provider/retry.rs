@@ -0,0 +1,505 @@
+use super::{IngressRule, Instance, KeyPair, LaunchSpec, Provider, RunResource, SpotRequest};
+use crate::ssh::{ConnectOptions, Session};
+use crate::{Backoff, Error};
+use rand::Rng;
+use slog::Logger;
+use std::collections::HashMap;
+use std::future::Future;
+use std::ops::Deref;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+// what the error messages of a call which was refused before it did anything contain
+const THROTTLED: &[&str] = &[
+    "Throttling",
+    "RequestLimitExceeded",
+    "TooManyRequests",
+    "SlowDown",
+];
+
+// what the error messages of a call which may well succeed when made again contain, besides
+// throttling; the call may have been carried out nonetheless
+const TRANSIENT: &[&str] = &[
+    // the service itself
+    "InternalError",
+    "InternalFailure",
+    "ServiceUnavailable",
+    "Unavailable",
+    // the network
+    "dns error",
+    "failed to lookup address",
+    "Pooled stream disconnected",
+    "broken pipe",
+    "connection reset",
+    "connection closed",
+    "timed out",
+    // resources still used by instances which are shutting down
+    "DependencyViolation",
+];
+
+// what the error messages of a call about resources which were just created, and are not
+// visible to every endpoint yet, contain; the same errors mean the resources are gone for good
+// once they are older
+const NOT_VISIBLE: &[&str] = &[
+    "InvalidInstanceID.NotFound",
+    "InvalidSpotInstanceRequestID.NotFound",
+    "InvalidGroup.NotFound",
+    "InvalidKeyPair.NotFound",
+    "InvalidVpcID.NotFound",
+    "InvalidSubnetID.NotFound",
+    "InvalidInternetGatewayID.NotFound",
+    "InvalidPlacementGroup.Unknown",
+];
+
+///Whether a provider error is likely to go away by making the same call again: throttling,
+///failures of the service or of the network, and resources still in use by instances which are
+///shutting down.
+pub fn is_transient(error: &Error) -> bool {
+    is_throttled(error) || mentions(error, TRANSIENT)
+}
+
+//Whether the call was refused by throttling, which is the only failure a call that creates
+//something without a client token can be made again after: after any other failure the
+//resource may exist already, and a second one would be left behind untracked.
+fn is_throttled(error: &Error) -> bool {
+    mentions(error, THROTTLED)
+}
+
+//Whether the call is worth making again when it names resources which were just created, and
+//may not be visible to every endpoint yet.
+fn is_transient_or_not_visible(error: &Error) -> bool {
+    is_transient(error) || mentions(error, NOT_VISIBLE)
+}
+
+fn mentions(error: &Error, markers: &[&str]) -> bool {
+    match error {
+        Error::Provider { source, .. } => {
+            let message = source.to_string();
+            markers.iter().any(|marker| message.contains(marker))
+        }
+        _ => false,
+    }
+}
+
+///How the calls to the provider are retried when they fail with a transient error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    ///how many times a call is made at most, 8 by default
+    pub attempts: usize,
+    ///how long to sleep between two attempts, from one second up to thirty by default; each
+    ///sleep is shortened by up to half at random, so that the calls of many machines spread out
+    pub backoff: Backoff,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 8,
+            backoff: Backoff {
+                initial: Duration::from_secs(1),
+                max: Duration::from_secs(30),
+                factor: 2,
+            },
+        }
+    }
+}
+
+impl RetryPolicy {
+    ///Make a call until it succeeds, fails with an error which is not `transient` for it, or runs
+    ///out of attempts.
+    pub(crate) async fn retry<T, F, Fut>(
+        &self,
+        logger: &Logger,
+        action: &str,
+        transient: fn(&Error) -> bool,
+        call: F,
+    ) -> Result<T, Error>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let mut delays = self.backoff.delays();
+        let mut attempt = 1;
+        loop {
+            match call().await {
+                Err(e) if attempt < self.attempts && transient(&e) => {
+                    let delay = jitter(delays.next().expect("the backoff delays never end"));
+                    debug!(logger, "retrying a call to the provider"; "action"=>action, "attempt"=>attempt, "delay"=>?delay, "error"=>%e);
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+fn jitter(delay: Duration) -> Duration {
+    delay.mul_f64(rand::rng().random_range(0.5..=1.0))
+}
+
+///A provider whose calls are retried as the [`RetryPolicy`] says. `run` wraps the provider it
+///is given in one.
+///
+///The calls are made again after the [`is_transient`] errors, except for the calls which create
+///a resource without a client token, which are only made again when they were throttled. The
+///calls which describe or tag resources are also made again when the resources are not found,
+///since they are made right after the resources were created.
+pub struct Retrying<'a, P> {
+    inner: Inner<'a, P>,
+    policy: RetryPolicy,
+    logger: Logger,
+}
+
+impl<'a, P: Provider> Retrying<'a, P> {
+    ///Wrap a provider.
+    pub fn new(inner: &'a P, policy: RetryPolicy, logger: Logger) -> Self {
+        Self {
+            inner: Inner::Borrowed(inner),
+            policy,
+            logger,
+        }
+    }
+
+    async fn retry<T, F, Fut>(&self, action: &str, call: F) -> Result<T, Error>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        self.policy
+            .retry(&self.logger, action, is_transient, call)
+            .await
+    }
+
+    async fn retry_throttled<T, F, Fut>(&self, action: &str, call: F) -> Result<T, Error>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        self.policy
+            .retry(&self.logger, action, is_throttled, call)
+            .await
+    }
+
+    async fn retry_fresh<T, F, Fut>(&self, action: &str, call: F) -> Result<T, Error>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        self.policy
+            .retry(&self.logger, action, is_transient_or_not_visible, call)
+            .await
+    }
+}
+
+//the provider a `Retrying` wraps, owned once it was detached
+enum Inner<'a, P> {
+    Borrowed(&'a P),
+    Detached(P),
+}
+
+impl<P> Deref for Inner<'_, P> {
+    type Target = P;
+
+    fn deref(&self) -> &P {
+        match self {
+            Inner::Borrowed(inner) => inner,
+            Inner::Detached(inner) => inner,
+        }
+    }
+}
+
+impl<P: Provider> Provider for Retrying<'_, P> {
+    async fn create_security_group(
+        &self,
+        name: &str,
+        description: &str,
+        vpc_id: Option<&str>,
+    ) -> Result<String, Error> {
+        self.retry_throttled("create the security group", || {
+            self.inner.create_security_group(name, description, vpc_id)
+        })
+        .await
+    }
+
+    async fn authorize_ingress(&self, group_id: &str, rules: &[IngressRule]) -> Result<(), Error> {
+        self.retry_fresh("authorize the security group ingress", || {
+            self.inner.authorize_ingress(group_id, rules)
+        })
+        .await
+    }
+
+    async fn delete_security_group(&self, group_id: &str) -> Result<(), Error> {
+        self.retry("delete the security group", || {
+            self.inner.delete_security_group(group_id)
+        })
+        .await
+    }
+
+    async fn create_key_pair(&self, name: &str) -> Result<KeyPair, Error> {
+        self.retry_throttled("create the key-pair", || self.inner.create_key_pair(name))
+            .await
+    }
+
+    async fn delete_key_pair(&self, name: &str) -> Result<(), Error> {
+        self.retry("delete the key-pair", || self.inner.delete_key_pair(name))
+            .await
+    }
+
+    async fn request_spot_instances(
+        &self,
+        spec: &LaunchSpec,
+        count: i64,
+        max_price: Option<f64>,
+    ) -> Result<Vec<String>, Error> {
+        self.retry("request spot instances", || {
+            self.inner.request_spot_instances(spec, count, max_price)
+        })
+        .await
+    }
+
+    async fn run_instances(&self, spec: &LaunchSpec, count: i64) -> Result<Vec<String>, Error> {
+        self.retry("launch on-demand instances", || {
+            self.inner.run_instances(spec, count)
+        })
+        .await
+    }
+
+    async fn describe_spot_instance_requests(
+        &self,
+        ids: &[String],
+    ) -> Result<Vec<SpotRequest>, Error> {
+        self.retry_fresh("describe the spot instance requests", || {
+            self.inner.describe_spot_instance_requests(ids)
+        })
+        .await
+    }
+
+    async fn cancel_spot_instance_requests(&self, ids: &[String]) -> Result<(), Error> {
+        self.retry("cancel the spot instance requests", || {
+            self.inner.cancel_spot_instance_requests(ids)
+        })
+        .await
+    }
+
+    async fn describe_instances(&self, ids: &[String]) -> Result<Vec<Instance>, Error> {
+        self.retry_fresh("describe the instances", || {
+            self.inner.describe_instances(ids)
+        })
+        .await
+    }
+
+    async fn terminate_instances(&self, ids: &[String]) -> Result<(), Error> {
+        self.retry("terminate the instances", || {
+            self.inner.terminate_instances(ids)
+        })
+        .await
+    }
+
+    async fn create_vpc(&self, cidr: &str) -> Result<String, Error> {
+        self.retry_throttled("create the vpc", || self.inner.create_vpc(cidr))
+            .await
+    }
+
+    async fn delete_vpc(&self, vpc_id: &str) -> Result<(), Error> {
+        self.retry("delete the vpc", || self.inner.delete_vpc(vpc_id))
+            .await
+    }
+
+    async fn create_internet_gateway(&self) -> Result<String, Error> {
+        self.retry_throttled("create the internet gateway", || {
+            self.inner.create_internet_gateway()
+        })
+        .await
+    }
+
+    async fn attach_internet_gateway(&self, gateway_id: &str, vpc_id: &str) -> Result<(), Error> {
+        self.retry_throttled("attach the internet gateway", || {
+            self.inner.attach_internet_gateway(gateway_id, vpc_id)
+        })
+        .await
+    }
+
+    async fn route_to_internet_gateway(&self, vpc_id: &str, gateway_id: &str) -> Result<(), Error> {
+        self.retry_throttled("route the vpc to the internet gateway", || {
+            self.inner.route_to_internet_gateway(vpc_id, gateway_id)
+        })
+        .await
+    }
+
+    async fn delete_internet_gateway(
+        &self,
+        gateway_id: &str,
+        vpc_id: Option<&str>,
+    ) -> Result<(), Error> {
+        self.retry("delete the internet gateway", || {
+            self.inner.delete_internet_gateway(gateway_id, vpc_id)
+        })
+        .await
+    }
+
+    async fn create_subnet(&self, vpc_id: &str, cidr: &str) -> Result<String, Error> {
+        self.retry_throttled("create the subnet", || {
+            self.inner.create_subnet(vpc_id, cidr)
+        })
+        .await
+    }
+
+    async fn delete_subnet(&self, subnet_id: &str) -> Result<(), Error> {
+        self.retry("delete the subnet", || self.inner.delete_subnet(subnet_id))
+            .await
+    }
+
+    async fn create_placement_group(&self, name: &str, strategy: &str) -> Result<(), Error> {
+        self.retry_throttled("create the placement group", || {
+            self.inner.create_placement_group(name, strategy)
+        })
+        .await
+    }
+
+    async fn delete_placement_group(&self, name: &str) -> Result<(), Error> {
+        self.retry("delete the placement group", || {
+            self.inner.delete_placement_group(name)
+        })
+        .await
+    }
+
+    async fn console_output(&self, instance_id: &str) -> Result<Option<String>, Error> {
+        self.retry_fresh("get the console output", || {
+            self.inner.console_output(instance_id)
+        })
+        .await
+    }
+
+    async fn tag_run(&self, ids: &[String], run_id: &str) -> Result<(), Error> {
+        self.retry_fresh("tag the resources", || self.inner.tag_run(ids, run_id))
+            .await
+    }
+
+    async fn list_run_resources(&self) -> Result<Vec<RunResource>, Error> {
+        self.retry("list the resources of the runs", || {
+            self.inner.list_run_resources()
+        })
+        .await
+    }
+
+    async fn spot_prices(&self, instance_types: &[String]) -> Result<HashMap<String, f64>, Error> {
+        self.retry("get the spot prices", || {
+            self.inner.spot_prices(instance_types)
+        })
+        .await
+    }
+
+    fn connect(
+        &self,
+        instance_id: &str,
+        public_ip: &str,
+        private_key: &Path,
+        options: &ConnectOptions,
+    ) -> Result<Session, Error> {
+        // the connection attempts are retried as the connect options say
+        self.inner
+            .connect(instance_id, public_ip, private_key, options)
+    }
+
+    fn schedule_shutdown(
+        &self,
+        instance_id: &str,
+        session: &Session,
+        deadline: Instant,
+    ) -> Result<(), Error> {
+        self.inner.schedule_shutdown(instance_id, session, deadline)
+    }
+
+    fn detached(&self) -> Result<Option<Self>, Error> {
+        Ok(self.inner.detached()?.map(|inner| Retrying {
+            inner: Inner::Detached(inner),
+            policy: self.policy.clone(),
+            logger: self.logger.clone(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slog::{Discard, o};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::runtime::Runtime;
+
+    fn policy(attempts: usize) -> RetryPolicy {
+        RetryPolicy {
+            attempts,
+            backoff: Backoff {
+                initial: Duration::from_millis(1),
+                max: Duration::from_millis(5),
+                factor: 2,
+            },
+        }
+    }
+
+    #[test]
+    fn transient_errors_are_retried() {
+        let logger = Logger::root(Discard, o!());
+        let calls = AtomicUsize::new(0);
+        let throttled = || async {
+            match calls.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => Err(Error::provider(
+                    "describe the instances",
+                    "RequestLimitExceeded: Request limit exceeded.",
+                )),
+                _ => Ok("described"),
+            }
+        };
+
+        let rt = Runtime::new().unwrap();
+        let result = rt.block_on(policy(5).retry(&logger, "describe", is_transient, throttled));
+        assert_eq!(result.unwrap(), "described");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        calls.store(0, Ordering::SeqCst);
+        let result = rt.block_on(policy(2).retry(&logger, "describe", is_transient, throttled));
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn permanent_errors_fail_at_once() {
+        let logger = Logger::root(Discard, o!());
+        let calls = AtomicUsize::new(0);
+        let rt = Runtime::new().unwrap();
+        let result: Result<(), Error> =
+            rt.block_on(policy(5).retry(&logger, "create", is_transient, || async {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Err(Error::provider(
+                    "create the key-pair",
+                    "InvalidKeyPair.Duplicate: the keypair 'burst' already exists",
+                ))
+            }));
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        assert!(is_transient(&Error::provider(
+            "delete the security group",
+            "DependencyViolation: resource sg-1 has a dependent object",
+        )));
+        assert!(!is_transient(&Error::Closure("Throttling".into())));
+    }
+
+    #[test]
+    fn calls_are_only_retried_when_it_is_safe() {
+        let reset = Error::provider("create the vpc", "connection reset by peer");
+        let throttled = Error::provider("create the vpc", "RequestLimitExceeded: slow down");
+        let gone = Error::provider(
+            "terminate the instances",
+            "InvalidInstanceID.NotFound: the instance ID 'i-1' does not exist",
+        );
+
+        // the vpc may have been created before the connection was reset
+        assert!(is_transient(&reset) && !is_throttled(&reset));
+        assert!(is_throttled(&throttled));
+        // an instance which is not found is only waited for right after it was launched
+        assert!(!is_transient(&gone));
+        assert!(is_transient_or_not_visible(&gone));
+    }
+}
@@ -0,0 +1,228 @@
+use crate::Error;
+use crate::provider::{Provider, ResourceKind, RetryPolicy, Retrying, RunResource};
+use crate::teardown::Teardown;
+use rand::{Rng, distr::Alphanumeric};
+use slog::Logger;
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+///A fresh run id: the unix time the run starts at, followed by a few random characters and, for
+///the runs which keep their cluster alive, the unix time the cluster expires at.
+pub(crate) fn new_run_id(expires_at: Option<u64>) -> String {
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let suffix: String = rand::rng()
+        .sample_iter(Alphanumeric)
+        .take(6)
+        .map(|c| char::from(c).to_ascii_lowercase())
+        .collect();
+    match expires_at {
+        Some(expires_at) => format!("{}-{}-{}", started, suffix, expires_at),
+        None => format!("{}-{}", started, suffix),
+    }
+}
+
+///When the run with the given id started, `None` for ids which were not made by `run`.
+fn started(run_id: &str) -> Option<SystemTime> {
+    let (secs, _) = run_id.split_once('-')?;
+    let secs = secs.parse().ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+///When the cluster kept alive by the run with the given id expires, `None` for runs which did not
+///keep their cluster alive.
+fn expires(run_id: &str) -> Option<SystemTime> {
+    let secs = run_id.splitn(3, '-').nth(2)?.parse().ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+///The resources of the runs which started at least `older_than` ago, sorted by run.
+///
+///The provider can not tell a run which died from one which is still going, so `older_than`
+///should be longer than the runs take. The clusters kept alive by
+///[`Lifecycle::KeepAlive`](crate::Lifecycle::KeepAlive) carry their expiry in their run id, and
+///are left out until they expire. Resources whose names do not hold a run id, such as the ones
+///of versions of this crate which did not tag their resources, are left out too.
+pub async fn leftovers<P: Provider>(
+    provider: &P,
+    older_than: Duration,
+) -> Result<Vec<RunResource>, Error> {
+    let now = SystemTime::now();
+    let mut resources = provider.list_run_resources().await?;
+    resources.retain(|resource| {
+        started(&resource.run_id)
+            .and_then(|started| now.duration_since(started).ok())
+            .is_some_and(|age| age >= older_than)
+            && expires(&resource.run_id).is_none_or(|expires| expires <= now)
+    });
+    resources.sort_by(|a, b| a.run_id.cmp(&b.run_id));
+    Ok(resources)
+}
+
+///Delete the [`leftovers`] of the runs which started at least `older_than` ago, and return
+///them.
+///
+///The resources of each run are released in the order its own teardown would have released
+///them, retrying the transient failures as the default [`RetryPolicy`] says. Every run is
+///attempted even if an earlier one failed, and the first failure is returned.
+///```rust, no_run
+/// # use burst::{provider::Ec2, Region};
+/// # use slog::{Discard, Logger, o};
+/// # use std::time::Duration;
+/// # use tokio::runtime::Runtime;
+/// let ec2 = Ec2::new(Region::UsEast1);
+/// let logger = Logger::root(Discard, o!());
+/// let rt = Runtime::new().unwrap();
+/// let reaped = rt
+///     .block_on(burst::reap(&ec2, Duration::from_secs(24 * 60 * 60), &logger))
+///     .unwrap();
+/// println!("{} resources deleted", reaped.len());
+///```
+pub async fn reap<P: Provider>(
+    provider: &P,
+    older_than: Duration,
+    logger: &Logger,
+) -> Result<Vec<RunResource>, Error> {
+    let provider = &Retrying::new(provider, RetryPolicy::default(), logger.clone());
+    let leftovers = leftovers(provider, older_than).await?;
+
+    let mut runs = BTreeMap::new();
+    for resource in &leftovers {
+        let teardown = runs.entry(resource.run_id.as_str()).or_insert_with(|| {
+            Teardown::new(provider, logger.new(o!("run_id"=>resource.run_id.clone())))
+        });
+        let id = &resource.id;
+        match &resource.kind {
+            ResourceKind::Instance => teardown.instances(std::slice::from_ref(id)),
+            ResourceKind::SpotRequest => teardown.spot_requests(std::slice::from_ref(id)),
+            ResourceKind::SecurityGroup => teardown.security_group(id),
+            ResourceKind::KeyPair => teardown.key_pair(id),
+            ResourceKind::PlacementGroup => teardown.placement_group(id),
+            ResourceKind::Subnet => teardown.subnet(id),
+            ResourceKind::InternetGateway { vpc_id } => {
+                teardown.internet_gateway(id, vpc_id.as_deref())
+            }
+            ResourceKind::Vpc => teardown.vpc(id),
+        }
+    }
+
+    let mut first_error = None;
+    for (run_id, mut teardown) in runs {
+        info!(logger, "deleting the resources left behind by a run"; "run_id"=>run_id);
+        if let Err(e) = teardown.release().await {
+            error!(logger, "some resources of the run could not be deleted"; "run_id"=>run_id, "error"=>?e);
+            first_error.get_or_insert(e);
+        }
+    }
+    first_error.map(Err).unwrap_or(Ok(leftovers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::{Fake, LaunchSpec};
+    use slog::Discard;
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn leftovers_of_old_runs_are_deleted() {
+        let logger = Logger::root(Discard, o!());
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let old = format!("{}-abcdef", now.as_secs() - 2 * 60 * 60);
+        let recent = format!("{}-ghijkl", now.as_secs() - 60);
+        let fake = Fake::new();
+
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            // a run which died after launching its instance in a network of its own
+            let vpc_id = fake.create_vpc("10.0.0.0/16").await.unwrap();
+            let gateway_id = fake.create_internet_gateway().await.unwrap();
+            fake.attach_internet_gateway(&gateway_id, &vpc_id)
+                .await
+                .unwrap();
+            let subnet_id = fake.create_subnet(&vpc_id, "10.0.0.0/16").await.unwrap();
+            let network = [vpc_id.clone(), gateway_id, subnet_id.clone()];
+            fake.tag_run(&network, &old).await.unwrap();
+            let group_id = fake
+                .create_security_group(&format!("burst_sg_{}", old), "", Some(&vpc_id))
+                .await
+                .unwrap();
+            fake.create_key_pair(&format!("burst_{}", old))
+                .await
+                .unwrap();
+            let spec = LaunchSpec {
+                ami: "ami-fake".to_string(),
+                instance_type: "t2.micro".to_string(),
+                security_group_id: group_id,
+                key_name: format!("burst_{}", old),
+                subnet_id: Some(subnet_id),
+                placement_group: None,
+                client_token: "token".to_string(),
+            };
+            let instances = fake.run_instances(&spec, 2).await.unwrap();
+            fake.tag_run(&instances, &old).await.unwrap();
+
+            // a run which may still be going, and a key pair without a run id
+            fake.create_key_pair(&format!("burst_{}", recent))
+                .await
+                .unwrap();
+            fake.create_key_pair("burst_mine").await.unwrap();
+
+            let found = leftovers(&fake, Duration::from_secs(60 * 60))
+                .await
+                .unwrap();
+            assert_eq!(found.len(), 7);
+            assert!(found.iter().all(|resource| resource.run_id == old));
+
+            let reaped = reap(&fake, Duration::from_secs(60 * 60), &logger)
+                .await
+                .unwrap();
+            assert_eq!(reaped, found);
+        });
+
+        assert!(fake.running_instances().is_empty());
+        assert!(fake.security_groups().is_empty());
+        assert!(fake.network_resources().is_empty());
+        let mut key_pairs = fake.key_pairs();
+        key_pairs.sort();
+        assert_eq!(
+            key_pairs,
+            [format!("burst_{}", recent), "burst_mine".to_string()]
+        );
+    }
+
+    #[test]
+    fn kept_alive_clusters_are_left_until_they_expire() {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let started = now.as_secs() - 2 * 60 * 60;
+        let alive = format!("{}-abcdef-{}", started, now.as_secs() + 60 * 60);
+        let expired = format!("{}-ghijkl-{}", started, now.as_secs() - 60);
+        let fake = Fake::new();
+
+        let rt = Runtime::new().unwrap();
+        let found = rt.block_on(async {
+            for run_id in [&alive, &expired] {
+                fake.create_key_pair(&format!("burst_{}", run_id))
+                    .await
+                    .unwrap();
+            }
+            leftovers(&fake, Duration::from_secs(60 * 60))
+                .await
+                .unwrap()
+        });
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].run_id, expired);
+    }
+
+    #[test]
+    fn only_kept_alive_runs_carry_their_expiry() {
+        assert!(expires(&new_run_id(None)).is_none());
+        let run_id = new_run_id(Some(1_700_000_000));
+        assert_eq!(
+            expires(&run_id),
+            Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+        );
+        assert!(started(&run_id).is_some());
+    }
+}
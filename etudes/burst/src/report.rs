@@ -0,0 +1,280 @@
+use crate::{Error, MachineInfo};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+///A measurement recorded by the main script.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Metric {
+    ///the name of the metric, such as `throughput`
+    pub name: String,
+    ///the measured value
+    pub value: f64,
+    ///the setup of the machine it was measured on, `None` for the metrics of the whole run
+    pub setup: Option<String>,
+    ///the position of the machine among the machines of its setup
+    pub index: Option<usize>,
+    ///the instance id of the machine
+    pub instance_id: Option<String>,
+    ///the instance type of the machine
+    pub instance_type: Option<String>,
+}
+
+///What a setup of the run was launched as.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SetupReport {
+    ///the instance type
+    pub instance_type: String,
+    ///the machine image, `None` for the setups of an attached cluster which the builder was not
+    ///given
+    pub ami: Option<String>,
+    ///the number of machines
+    pub machines: usize,
+}
+
+///The report of a run: what it ran on, how long the main script took and what it measured.
+///
+///`run` keeps the report of the last run, see
+///[`BurstBuilder::last_report`](crate::BurstBuilder::last_report), and writes it as JSON and CSV
+///if the builder has a report directory.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Report {
+    ///the id of the run which launched the cluster
+    pub run_id: String,
+    ///the position of the iteration in its sweep, `None` for a single run
+    pub iteration: Option<usize>,
+    ///the parameters of the iteration, see [`Sweep`](crate::Sweep)
+    pub parameters: BTreeMap<String, String>,
+    ///when the main script started, in seconds since the unix epoch
+    pub started_at: u64,
+    ///how long the main script took, in seconds
+    pub duration_secs: f64,
+    ///the error the run failed with, if it did
+    pub error: Option<String>,
+    ///the setups, by name
+    pub setups: BTreeMap<String, SetupReport>,
+    ///the metrics, in the order they were recorded
+    pub metrics: Vec<Metric>,
+}
+
+impl Report {
+    ///The report as pretty printed JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("a report is always serializable")
+    }
+
+    ///The metrics as CSV, one row per metric with the run id, the parameters of the iteration
+    ///and the machine it was measured on, whose columns are empty for the metrics of the whole run.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("run_id,");
+        for name in self.parameters.keys() {
+            csv.push_str(&csv_field(name));
+            csv.push(',');
+        }
+        csv.push_str("setup,index,instance_id,instance_type,name,value\n");
+        for metric in &self.metrics {
+            csv.push_str(&csv_field(&self.run_id));
+            csv.push(',');
+            for value in self.parameters.values() {
+                csv.push_str(&csv_field(value));
+                csv.push(',');
+            }
+            let index = metric.index.map(|index| index.to_string());
+            let fields = [
+                metric.setup.as_deref(),
+                index.as_deref(),
+                metric.instance_id.as_deref(),
+                metric.instance_type.as_deref(),
+                Some(metric.name.as_str()),
+            ];
+            for field in fields {
+                csv.push_str(&csv_field(field.unwrap_or_default()));
+                csv.push(',');
+            }
+            let _ = writeln!(csv, "{}", metric.value);
+        }
+        csv
+    }
+
+    ///Write the report to `burst_<run id>.json` and `burst_<run id>.csv` in the given directory,
+    ///or to `burst_<run id>_<iteration>.json` and `.csv` for an iteration of a sweep.
+    pub fn write_to(&self, dir: &Path) -> Result<(), Error> {
+        let stem = match self.iteration {
+            Some(iteration) => format!("burst_{}_{}", self.run_id, iteration),
+            None => format!("burst_{}", self.run_id),
+        };
+        std::fs::create_dir_all(dir)
+            .map_err(|e| Error::io(format!("create the report directory {}", dir.display()), e))?;
+        for (extension, content) in [("json", self.to_json()), ("csv", self.to_csv())] {
+            let path = dir.join(format!("{}.{}", stem, extension));
+            std::fs::write(&path, content)
+                .map_err(|e| Error::io(format!("write the report {}", path.display()), e))?;
+        }
+        Ok(())
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+///Where the metrics of a run go, shared by its cluster and all of its machines.
+#[derive(Clone, Default)]
+pub(crate) struct Recorder {
+    inner: Arc<Mutex<Recorded>>,
+}
+
+#[derive(Default)]
+struct Recorded {
+    started: Option<(SystemTime, Duration)>,
+    metrics: Vec<Metric>,
+}
+
+impl Recorder {
+    fn lock(&self) -> MutexGuard<'_, Recorded> {
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub(crate) fn record(&self, name: &str, value: f64, machine: Option<&MachineInfo>) {
+        self.lock().metrics.push(Metric {
+            name: name.to_string(),
+            value,
+            setup: machine.map(|machine| machine.name.clone()),
+            index: machine.map(|machine| machine.index),
+            instance_id: machine.map(|machine| machine.instance_id.clone()),
+            instance_type: machine.map(|machine| machine.instance_type.clone()),
+        });
+    }
+
+    ///Forget what was recorded, for the next iteration of a sweep.
+    pub(crate) fn clear(&self) {
+        *self.lock() = Recorded::default();
+    }
+
+    ///Note when the main script started and how long it took.
+    pub(crate) fn script_ran(&self, started: SystemTime, took: Duration) {
+        self.lock().started = Some((started, took));
+    }
+
+    ///The report of the run, `None` if its main script did not run.
+    pub(crate) fn report(
+        &self,
+        run_id: &str,
+        setups: BTreeMap<String, SetupReport>,
+        error: Option<String>,
+    ) -> Option<Report> {
+        let recorded = self.lock();
+        let (started, took) = recorded.started?;
+        Some(Report {
+            run_id: run_id.to_string(),
+            iteration: None,
+            parameters: BTreeMap::new(),
+            started_at: started
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs()),
+            duration_secs: took.as_secs_f64(),
+            error,
+            setups,
+            metrics: recorded.metrics.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::Fake;
+    use crate::testing::{builder, run_torn_down};
+
+    #[test]
+    fn metrics_are_written_as_csv() {
+        let recorder = Recorder::default();
+        let machine = MachineInfo {
+            name: "client".to_string(),
+            index: 1,
+            instance_id: "i-1".to_string(),
+            instance_type: "t2.micro".to_string(),
+            availability_zone: None,
+            private_ip: "172.31.0.1".to_string(),
+            public_dns: "ec2-1.compute.fake".to_string(),
+            public_ip: "203.0.113.1".to_string(),
+        };
+        recorder.record("latency, p99", 12.5, Some(&machine));
+        recorder.record("throughput", 1000.0, None);
+        assert_eq!(recorder.report("1-run", BTreeMap::new(), None), None);
+
+        recorder.script_ran(UNIX_EPOCH + Duration::from_secs(60), Duration::from_secs(2));
+        let report = recorder.report("1-run", BTreeMap::new(), None).unwrap();
+        assert_eq!(report.started_at, 60);
+        assert_eq!(
+            report.to_csv(),
+            "run_id,setup,index,instance_id,instance_type,name,value\n\
+             1-run,client,1,i-1,t2.micro,\"latency, p99\",12.5\n\
+             1-run,,,,,throughput,1000\n"
+        );
+
+        let mut report = report;
+        report
+            .parameters
+            .insert("payload".to_string(), "1k".to_string());
+        assert!(report.to_csv().starts_with(
+            "run_id,payload,setup,index,instance_id,instance_type,name,value\n\
+                              1-run,1k,client,1,"
+        ));
+    }
+
+    #[test]
+    fn recorded_metrics_are_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut builder = builder(&[("client", 2)]);
+        builder.set_report_dir(dir.path());
+        let fake = Fake::new();
+
+        let result = run_torn_down(
+            &fake,
+            builder.run_with(&fake, |vms| {
+                for vm in &vms["client"] {
+                    vm.record("latency_ms", 2.0 + vm.index as f64);
+                }
+                vms.record("throughput", 500.0);
+                Err("the server fell over".into())
+            }),
+        );
+        assert!(matches!(result, Err(Error::Script(_))));
+
+        let report = builder.last_report().unwrap();
+        assert_eq!(report.setups["client"].ami.as_deref(), Some("ami-fake"));
+        assert_eq!(report.setups["client"].machines, 2);
+        assert!(report.error.is_some());
+        let metrics: Vec<(Option<usize>, &str, f64)> = report
+            .metrics
+            .iter()
+            .map(|metric| (metric.index, metric.name.as_str(), metric.value))
+            .collect();
+        assert_eq!(
+            metrics,
+            [
+                (Some(0), "latency_ms", 2.0),
+                (Some(1), "latency_ms", 3.0),
+                (None, "throughput", 500.0)
+            ]
+        );
+        let csv = std::fs::read_to_string(dir.path().join(format!("burst_{}.csv", report.run_id)))
+            .unwrap();
+        assert_eq!(csv, report.to_csv());
+        assert!(
+            dir.path()
+                .join(format!("burst_{}.json", report.run_id))
+                .exists()
+        );
+    }
+}
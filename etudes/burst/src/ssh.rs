@@ -1,86 +1,624 @@
-use failure::{self, ResultExt};
-use ssh2;
+//!Sessions to the machines of a burst.
+//!
+//!A [`Session`] is usually an ssh connection, but it can also run commands as local
+//!subprocesses inside a sandbox directory, which lets setup closures run unchanged on the
+//!developer machine.
+
+use crate::{Backoff, Error};
+use pool::Pool;
 use std::{
-    net::{SocketAddr, TcpStream},
+    net::{IpAddr, SocketAddr, TcpStream},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
     time::{Duration, Instant},
 };
 
+mod host_key;
+mod pool;
+mod process;
+mod transfer;
+
+pub use host_key::{HostKeyCheck, keys_from_console};
+pub use process::{Process, Stream};
+
+///How to reach the ssh server of a machine.
+///```rust
+/// # use burst::ssh::ConnectOptions;
+/// let ubuntu = ConnectOptions {
+///     username: "ubuntu".to_string(),
+///     ..Default::default()
+/// };
+///```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectOptions {
+    ///the user to log in as, `ec2-user` by default; Ubuntu images use `ubuntu` and Debian
+    ///images `admin`
+    pub username: String,
+    ///the port of the ssh server, 22 by default
+    pub port: u16,
+    ///how long a single tcp connection attempt may take, 3 seconds by default
+    pub connect_timeout: Duration,
+    ///for how long failed connection attempts are retried, one minute by default, as the ssh
+    ///server of a fresh instance may take a while to come up and accept the key
+    pub retry_for: Duration,
+    ///how long to sleep between two connection attempts
+    pub backoff: Backoff,
+    ///how many connections may be open to run commands at the same time, 4 by default; more
+    ///commands wait for one of them to be free
+    pub connections: usize,
+    ///how the host key is checked, which `run` sets from the host key policy of the builder;
+    ///any key is accepted by default
+    pub host_key: HostKeyCheck,
+}
+
+impl Default for ConnectOptions {
+    fn default() -> Self {
+        Self {
+            username: "ec2-user".to_string(),
+            port: 22,
+            connect_timeout: Duration::from_secs(3),
+            retry_for: Duration::from_secs(60),
+            backoff: Backoff::default(),
+            connections: 4,
+            host_key: HostKeyCheck::default(),
+        }
+    }
+}
+
+///A session to a machine which commands can be executed on.
+///
+///Clones share the same underlying connections. Commands run over a small pool of ssh
+///connections, so that several of them can run at the same time, and a connection which was
+///closed, by a reboot or a network failure, is opened again as the connect options say.
+#[derive(Clone)]
 pub struct Session {
-    ssh: ssh2::Session,
+    transport: Transport,
+    shutdown: Arc<Mutex<Option<(Instant, Schedule)>>>,
+}
+
+//How a machine is made to shut itself down, given the minutes left before its deadline.
+type Schedule = Arc<dyn Fn(&Session, u64) -> Result<(), Error> + Send + Sync>;
+
+#[derive(Clone)]
+enum Transport {
+    Ssh(Arc<Pool>),
+    Subprocess(PathBuf),
 }
 
 impl Session {
-    pub(crate) fn connect(
+    ///Connect over ssh to `addr` and authenticate as `username` with the given private key.
+    pub fn connect(
         addr: SocketAddr,
+        username: &str,
         private_key_path: &Path,
-    ) -> Result<Self, failure::Error> {
+    ) -> Result<Self, Error> {
+        let options = ConnectOptions {
+            username: username.to_string(),
+            port: addr.port(),
+            ..Default::default()
+        };
+        Self::connect_with(addr.ip(), private_key_path, &options)
+    }
+
+    ///Connect over ssh to `ip` and authenticate with the given private key, as the options say.
+    pub fn connect_with(
+        ip: IpAddr,
+        private_key_path: &Path,
+        options: &ConnectOptions,
+    ) -> Result<Self, Error> {
+        let addr = SocketAddr::new(ip, options.port);
+        let pool = Pool::connect(addr, private_key_path.to_path_buf(), options.clone())?;
+        Ok(Self {
+            transport: Transport::Ssh(Arc::new(pool)),
+            shutdown: Default::default(),
+        })
+    }
+
+    fn open(
+        addr: SocketAddr,
+        private_key_path: &Path,
+        options: &ConnectOptions,
+    ) -> Result<ssh2::Session, Error> {
         let start = Instant::now();
-        let tcp = loop {
-            match TcpStream::connect_timeout(&addr, Duration::from_secs(3)) {
-                Ok(tcp) => break tcp,
-                Err(_) if start.elapsed() <= Duration::from_secs(60) => {}
-                Err(e) => {
-                    Err(failure::Error::from(e).context("Failed to stablish tcp connection"))?
+        let mut delays = options.backoff.delays();
+        loop {
+            match Self::try_open(addr, private_key_path, options) {
+                Ok(session) => return Ok(session),
+                // a different host key is not going to go away by trying again
+                Err(e @ Error::HostKey { .. }) => return Err(e),
+                Err(_) if start.elapsed() < options.retry_for => {
+                    std::thread::sleep(delays.next().expect("the backoff delays never end"));
                 }
+                Err(e) => return Err(e),
             }
+        }
+    }
+
+    fn try_open(
+        addr: SocketAddr,
+        private_key_path: &Path,
+        options: &ConnectOptions,
+    ) -> Result<ssh2::Session, Error> {
+        let connect_error = |source: Box<dyn std::error::Error + Send + Sync>| Error::Connect {
+            address: addr.to_string(),
+            source,
         };
+        let tcp = TcpStream::connect_timeout(&addr, options.connect_timeout)
+            .map_err(|e| connect_error(e.into()))?;
 
-        let mut session = ssh2::Session::new().context("Failed to initilize a new ssh session")?;
+        let mut session = ssh2::Session::new().map_err(|e| connect_error(e.into()))?;
         session.set_tcp_stream(tcp);
-        session
-            .handshake()
-            .context("Failed to perform handshake on ssh session")?;
+        session.handshake().map_err(|e| connect_error(e.into()))?;
+        host_key::verify(&session, addr, &options.host_key)?;
 
-        //let private_key_path = Path::new("/home/meyar/.ssh/bonjour.pem");
         session
-            .userauth_pubkey_file("ec2-user", None, private_key_path, None)
-            .context("Failed to authenticate the ssh")?;
+            .userauth_pubkey_file(&options.username, None, private_key_path, None)
+            .map_err(|e| Error::Auth {
+                address: addr.to_string(),
+                username: options.username.clone(),
+                source: e.into(),
+            })?;
+        Ok(session)
+    }
+
+    ///Create a session which runs every command with `sh -c` as a local subprocess inside the
+    ///given directory.
+    pub fn subprocess<P: Into<PathBuf>>(dir: P) -> Self {
+        Self {
+            transport: Transport::Subprocess(dir.into()),
+            shutdown: Default::default(),
+        }
+    }
+
+    ///Run a command and return its exit status and output, whatever the exit status is.
+    pub fn exec(&self, command: &str) -> Result<Output, Error> {
+        let start = Instant::now();
+        let output = match &self.transport {
+            Transport::Ssh(pool) => pool.with(
+                |session| Ok::<_, ssh2::Error>((session.clone(), session.channel_session()?)),
+                |(session, channel)| Self::ssh_exec(&session, channel, command),
+            ),
+            Transport::Subprocess(dir) => std::process::Command::new("sh")
+                .arg("-c")
+                .arg(command)
+                .current_dir(dir)
+                .output()
+                .map(|output| {
+                    (
+                        output.status.code(),
+                        String::from_utf8_lossy(&output.stdout).into_owned(),
+                        String::from_utf8_lossy(&output.stderr).into_owned(),
+                    )
+                })
+                .map_err(Into::into),
+        };
+        let (status, stdout, stderr) = output.map_err(|source| Error::Command {
+            command: command.to_string(),
+            source,
+        })?;
+        Ok(Output {
+            status,
+            stdout,
+            stderr,
+            duration: start.elapsed(),
+        })
+    }
+
+    ///Run a command, failing with [`Error::Exit`] unless it exits with status 0.
+    pub fn exec_checked(&self, command: &str) -> Result<Output, Error> {
+        let output = self.exec(command)?;
+        if !output.success() {
+            return Err(Error::Exit {
+                command: command.to_string(),
+                output,
+            });
+        }
+        Ok(output)
+    }
+
+    ///Run a command and return what it wrote to its standard output, failing with
+    ///[`Error::Exit`] unless it exits with status 0.
+    pub fn cmd(&self, command: &str) -> Result<String, Error> {
+        Ok(self.exec_checked(command)?.stdout)
+    }
+
+    ///Start a command in the background and hand every line it writes to `on_line` as soon as
+    ///it arrives, until it exits or is killed through the returned [`Process`].
+    ///```rust
+    /// # use burst::ssh::{Session, Stream};
+    /// # let dir = tempfile::tempdir().unwrap();
+    /// # let session = Session::subprocess(dir.path());
+    /// let server = session.spawn("echo listening; sleep 60", |stream, line| {
+    ///     if stream == Stream::Stdout {
+    ///         println!("server: {}", line);
+    ///     }
+    /// })?;
+    /// // run the benchmark against the server ...
+    /// server.kill()?;
+    /// # Ok::<(), burst::Error>(())
+    ///```
+    pub fn spawn<F>(&self, command: &str, on_line: F) -> Result<Process, Error>
+    where
+        F: FnMut(Stream, &str) + Send + 'static,
+    {
+        let on_line = Arc::new(Mutex::new(on_line));
+        match &self.transport {
+            Transport::Ssh(pool) => Process::ssh(
+                self,
+                pool.addr,
+                &pool.private_key,
+                &pool.options,
+                command,
+                on_line,
+            ),
+            Transport::Subprocess(dir) => Process::subprocess(self, dir, command, on_line),
+        }
+    }
+
+    ///Like [`Session::spawn`], logging every line to `logger`.
+    pub fn spawn_logged(&self, command: &str, logger: &slog::Logger) -> Result<Process, Error> {
+        let logger = logger.new(o!("command"=>command.to_string()));
+        self.spawn(command, move |stream, line| match stream {
+            Stream::Stdout => info!(logger, "{}", line),
+            Stream::Stderr => warn!(logger, "{}", line),
+        })
+    }
+
+    ///Copy a local file or directory, with its permissions, to the given path on the machine.
+    ///Relative paths on the machine are taken from the home directory of the user.
+    pub fn upload<L, R>(&self, local: L, remote: R) -> Result<(), Error>
+    where
+        L: AsRef<Path>,
+        R: AsRef<Path>,
+    {
+        let (local, remote) = (local.as_ref(), remote.as_ref());
+        let copied = match &self.transport {
+            Transport::Ssh(pool) => pool.with(ssh2::Session::sftp, |sftp| {
+                transfer::upload(&sftp, local, remote)
+            }),
+            Transport::Subprocess(dir) => transfer::copy(local, &dir.join(remote)),
+        };
+        copied.map_err(|source| Error::Transfer {
+            from: local.display().to_string(),
+            to: format!("{}:{}", self.address(), remote.display()),
+            source,
+        })
+    }
+
+    ///Copy a file or directory of the machine, with its permissions, to the given local path.
+    pub fn download<R, L>(&self, remote: R, local: L) -> Result<(), Error>
+    where
+        R: AsRef<Path>,
+        L: AsRef<Path>,
+    {
+        let (remote, local) = (remote.as_ref(), local.as_ref());
+        let copied = match &self.transport {
+            Transport::Ssh(pool) => pool.with(ssh2::Session::sftp, |sftp| {
+                transfer::download(&sftp, remote, local)
+            }),
+            Transport::Subprocess(dir) => transfer::copy(&dir.join(remote), local),
+        };
+        copied.map_err(|source| Error::Transfer {
+            from: format!("{}:{}", self.address(), remote.display()),
+            to: local.display().to_string(),
+            source,
+        })
+    }
+
+    ///Reboot the machine, such as after installing a kernel or a driver, and wait until it is
+    ///back with a new boot id. It fails once `timeout` has passed since the reboot, when the
+    ///attempt to reach the machine which is under way by then gives up. The shutdown scheduled
+    ///by [`Session::shut_down_at`] is scheduled again once the machine is back. Sessions running
+    ///their commands as local subprocesses are not rebooted, but their shutdown is scheduled
+    ///again all the same.
+    ///```rust, no_run
+    /// # fn f(ssh: &mut burst::ssh::Session) -> Result<(), burst::Error> {
+    /// # use std::time::Duration;
+    /// ssh.cmd("sudo yum install -y kernel-devel")?;
+    /// ssh.reboot_and_wait(Duration::from_secs(300))?;
+    /// # Ok(())
+    /// # }
+    ///```
+    pub fn reboot_and_wait(&self, timeout: Duration) -> Result<(), Error> {
+        const BOOT_ID: &str = "cat /proc/sys/kernel/random/boot_id";
+        let Transport::Ssh(pool) = &self.transport else {
+            return self.schedule_shutdown_again();
+        };
+        let boot_id = self.cmd(BOOT_ID)?;
+        // the reboot closes the connection, which may well fail the command itself
+        if let Ok(output) = self.exec("sudo shutdown -r now")
+            && !output.success()
+        {
+            return Err(Error::Exit {
+                command: "sudo shutdown -r now".to_string(),
+                output,
+            });
+        }
+        pool.discard_idle();
+
+        let start = Instant::now();
+        let mut delays = pool.options.backoff.delays();
+        loop {
+            std::thread::sleep(delays.next().expect("the backoff delays never end"));
+            let last = match self.cmd(BOOT_ID) {
+                // the reboot cancelled the shutdown the machine was to do at the deadline
+                Ok(id) if id != boot_id => return self.schedule_shutdown_again(),
+                Ok(_) => "the machine did not go down yet".to_string(),
+                Err(e) => e.to_string(),
+            };
+            if start.elapsed() >= timeout {
+                return Err(Error::Connect {
+                    address: pool.addr.to_string(),
+                    source: format!(
+                        "the machine did not come back from its reboot within {:?}: {}",
+                        timeout, last
+                    )
+                    .into(),
+                });
+            }
+        }
+    }
+
+    ///Make the machine shut itself down at the deadline with `schedule`, which is handed the
+    ///minutes left, rounded up. It is called again when [`Session::reboot_and_wait`] brings the
+    ///machine back, since a reboot cancels the pending shutdown.
+    pub fn shut_down_at(
+        &self,
+        deadline: Instant,
+        schedule: impl Fn(&Session, u64) -> Result<(), Error> + Send + Sync + 'static,
+    ) -> Result<(), Error> {
+        schedule(self, minutes_until(deadline))?;
+        *self.lock_shutdown() = Some((deadline, Arc::new(schedule)));
+        Ok(())
+    }
 
-        Ok(Self { ssh: session })
+    fn schedule_shutdown_again(&self) -> Result<(), Error> {
+        let shutdown = self.lock_shutdown().clone();
+        match shutdown {
+            Some((deadline, schedule)) => schedule(self, minutes_until(deadline)),
+            None => Ok(()),
+        }
     }
 
-    pub fn cmd(&self, command: &str) -> Result<String, failure::Error> {
+    fn lock_shutdown(&self) -> std::sync::MutexGuard<'_, Option<(Instant, Schedule)>> {
+        self.shutdown
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn address(&self) -> String {
+        match &self.transport {
+            Transport::Ssh(pool) => pool.addr.ip().to_string(),
+            Transport::Subprocess(dir) => dir.display().to_string(),
+        }
+    }
+
+    ///Like [`Session::exec`], on the blocking thread pool of the tokio runtime, so that the
+    ///calling task is free to drive other sessions meanwhile.
+    pub async fn exec_async(&self, command: &str) -> Result<Output, Error> {
+        self.blocking(command, Session::exec).await
+    }
+
+    ///Like [`Session::cmd`], on the blocking thread pool of the tokio runtime.
+    pub async fn cmd_async(&self, command: &str) -> Result<String, Error> {
+        self.blocking(command, Session::cmd).await
+    }
+
+    async fn blocking<T: Send + 'static>(
+        &self,
+        command: &str,
+        run: fn(&Session, &str) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let session = self.clone();
+        let owned = command.to_string();
+        tokio::task::spawn_blocking(move || run(&session, &owned))
+            .await
+            .map_err(|e| Error::Command {
+                command: command.to_string(),
+                source: e.into(),
+            })?
+    }
+
+    fn ssh_exec(
+        session: &ssh2::Session,
+        mut channel: ssh2::Channel,
+        command: &str,
+    ) -> Result<(Option<i32>, String, String), Box<dyn std::error::Error + Send + Sync>> {
+        channel.exec(command)?;
+
+        // a command filling the window of one stream stalls until it is read, so both are read
+        // as the output comes instead of one to its end before the other
+        session.set_blocking(false);
+        let read = Self::read_outputs(&mut channel);
+        session.set_blocking(true);
+        let (stdout, stderr) = read?;
+        channel.wait_close()?;
+
+        // a command killed by a signal has no exit status of its own
+        let status = match channel.exit_signal()?.exit_signal {
+            Some(_) => None,
+            None => Some(channel.exit_status()?),
+        };
+        Ok((status, stdout, stderr))
+    }
+
+    //Read the standard output and error of a non-blocking channel until it reaches its end.
+    fn read_outputs(channel: &mut ssh2::Channel) -> std::io::Result<(String, String)> {
         use std::io::Read;
-        let mut channel = self
-            .ssh
-            .channel_session()
-            .map_err(failure::Error::from)
-            .map_err(|e| {
-                e.context(format!(
-                    "failed to stablish a session channel for `{}`",
-                    command
-                ))
-            })?;
-        channel
-            .exec(command)
-            .context(format!("failed to execute the command `{}`", command))?;
-
-        let mut s = String::new();
-        channel
-            .read_to_string(&mut s)
-            .context(format!("failed to read the `{}` command result", command))?;
-        channel.wait_close().context(format!(
-            "failed to close the channel for `{}` command",
-            command
-        ))?;
-
-        //TODO: ensure the channel exit status
-        Ok(s)
+        let mut outputs = [
+            (channel.stream(0), Vec::new()),
+            (channel.stderr(), Vec::new()),
+        ];
+        let mut buffer = [0; 4096];
+        loop {
+            let mut progressed = false;
+            for (reader, output) in &mut outputs {
+                match reader.read(&mut buffer) {
+                    Ok(0) => {}
+                    Ok(n) => {
+                        progressed = true;
+                        output.extend_from_slice(&buffer[..n]);
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            if !progressed {
+                if channel.eof() {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        }
+        let [(_, stdout), (_, stderr)] = outputs;
+        Ok((
+            String::from_utf8_lossy(&stdout).into_owned(),
+            String::from_utf8_lossy(&stderr).into_owned(),
+        ))
     }
 }
 
-use std::ops::{Deref, DerefMut};
-use std::path::Path;
+//The whole minutes left before the deadline, at least one.
+fn minutes_until(deadline: Instant) -> u64 {
+    deadline
+        .saturating_duration_since(Instant::now())
+        .as_secs()
+        .div_ceil(60)
+        .max(1)
+}
+
+///What a command did, as returned by [`Session::exec`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Output {
+    ///the exit status, `None` if the command was killed by a signal
+    pub status: Option<i32>,
+    ///what the command wrote to its standard output
+    pub stdout: String,
+    ///what the command wrote to its standard error
+    pub stderr: String,
+    ///how long the command took
+    pub duration: Duration,
+}
 
-impl Deref for Session {
-    type Target = ssh2::Session;
-    fn deref(&self) -> &Self::Target {
-        &self.ssh
+impl Output {
+    ///Whether the command exited with status 0.
+    pub fn success(&self) -> bool {
+        self.status == Some(0)
     }
 }
 
-impl DerefMut for Session {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.ssh
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exec_reports_the_status_and_both_outputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let session = Session::subprocess(dir.path());
+
+        let output = session.exec("echo out; echo err >&2; exit 3").unwrap();
+        assert_eq!(output.status, Some(3));
+        assert_eq!(output.stdout, "out\n");
+        assert_eq!(output.stderr, "err\n");
+        assert!(!output.success());
+
+        let Err(Error::Exit { output, .. }) = session.cmd("false") else {
+            panic!("a failing command must be an error");
+        };
+        assert_eq!(output.status, Some(1));
+        assert_eq!(session.cmd("echo ok").unwrap(), "ok\n");
+    }
+
+    #[test]
+    fn connection_attempts_are_retried_for_a_while() {
+        let port = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().port()
+        };
+        let options = ConnectOptions {
+            port,
+            retry_for: Duration::from_millis(300),
+            backoff: Backoff {
+                initial: Duration::from_millis(10),
+                max: Duration::from_millis(50),
+                factor: 2,
+            },
+            ..Default::default()
+        };
+
+        let start = Instant::now();
+        let result = Session::connect_with([127, 0, 0, 1].into(), Path::new("key"), &options);
+        assert!(matches!(result, Err(Error::Connect { .. })));
+        assert!(start.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[test]
+    fn directories_are_copied_with_their_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let local = tempfile::tempdir().unwrap();
+        std::fs::create_dir(local.path().join("bin")).unwrap();
+        let tool = local.path().join("bin/tool");
+        std::fs::write(&tool, "#!/bin/sh\necho tool ran > result\n").unwrap();
+        std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o750)).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let session = Session::subprocess(dir.path());
+
+        session.upload(local.path().join("bin"), "bin").unwrap();
+        session.cmd("bin/tool").unwrap();
+        session
+            .download("result", local.path().join("result"))
+            .unwrap();
+
+        let mode = std::fs::metadata(dir.path().join("bin/tool"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o750);
+        assert_eq!(
+            std::fs::read_to_string(local.path().join("result")).unwrap(),
+            "tool ran\n"
+        );
+        assert!(matches!(
+            session.download("missing", local.path().join("missing")),
+            Err(Error::Transfer { .. })
+        ));
+    }
+
+    #[test]
+    fn spawned_process_streams_its_output_until_killed() {
+        let dir = tempfile::tempdir().unwrap();
+        let session = Session::subprocess(dir.path());
+
+        let (lines, received) = std::sync::mpsc::channel();
+        let process = session
+            .spawn(
+                "echo ready; echo warming up >&2; sleep 60",
+                move |stream, line| {
+                    lines.send((stream, line.to_string())).unwrap();
+                },
+            )
+            .unwrap();
+
+        let mut seen = vec![
+            received.recv_timeout(Duration::from_secs(5)).unwrap(),
+            received.recv_timeout(Duration::from_secs(5)).unwrap(),
+        ];
+        seen.sort_by_key(|(stream, _)| *stream == Stream::Stderr);
+        assert_eq!(
+            seen,
+            [
+                (Stream::Stdout, "ready".to_string()),
+                (Stream::Stderr, "warming up".to_string())
+            ]
+        );
+        assert!(!process.is_finished());
+
+        let start = Instant::now();
+        assert_eq!(process.kill().unwrap(), None);
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let process = session.spawn("exit 4", |_, _| {}).unwrap();
+        assert_eq!(process.wait().unwrap(), Some(4));
     }
 }
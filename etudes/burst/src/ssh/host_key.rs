@@ -0,0 +1,126 @@
+use crate::{BoxError, Error};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+///How the host key a machine presents is checked when connecting to it.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum HostKeyCheck {
+    ///Accept any host key.
+    #[default]
+    AcceptAny,
+    ///Trust the key an address presents the first time, recording it in the given known_hosts
+    ///file, and refuse any other key for that address afterwards.
+    TrustOnFirstUse(PathBuf),
+    ///Accept only these keys, base64 encoded as in an `authorized_keys` or `known_hosts` line.
+    Keys(Vec<String>),
+}
+
+// the known_hosts files are read, checked and written back as a whole, which must not interleave
+static KNOWN_HOSTS: Mutex<()> = Mutex::new(());
+
+///Check the host key of a session which just did its handshake.
+pub(super) fn verify(
+    session: &ssh2::Session,
+    addr: SocketAddr,
+    check: &HostKeyCheck,
+) -> Result<(), Error> {
+    let host_key_error = |source: BoxError| Error::HostKey {
+        address: addr.to_string(),
+        source,
+    };
+    if *check == HostKeyCheck::AcceptAny {
+        return Ok(());
+    }
+    let (key, key_type) = session
+        .host_key()
+        .ok_or_else(|| host_key_error("the server presented no host key".into()))?;
+
+    match check {
+        HostKeyCheck::AcceptAny => Ok(()),
+        HostKeyCheck::Keys(keys) => {
+            let presented = base64::encode(key);
+            if keys.contains(&presented) {
+                Ok(())
+            } else {
+                Err(host_key_error(
+                    "the host key is not one of the keys the machine reported".into(),
+                ))
+            }
+        }
+        HostKeyCheck::TrustOnFirstUse(path) => {
+            trust_on_first_use(session, addr, key, key_type, path).map_err(host_key_error)
+        }
+    }
+}
+
+fn trust_on_first_use(
+    session: &ssh2::Session,
+    addr: SocketAddr,
+    key: &[u8],
+    key_type: ssh2::HostKeyType,
+    path: &Path,
+) -> Result<(), BoxError> {
+    let _guard = KNOWN_HOSTS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let mut known_hosts = session.known_hosts()?;
+    if path.exists() {
+        known_hosts.read_file(path, ssh2::KnownHostFileKind::OpenSSH)?;
+    }
+
+    let host = addr.ip().to_string();
+    match known_hosts.check_port(&host, addr.port(), key) {
+        ssh2::CheckResult::Match => Ok(()),
+        ssh2::CheckResult::Mismatch => Err(format!(
+            "the host key differs from the one recorded in {}",
+            path.display()
+        )
+        .into()),
+        ssh2::CheckResult::NotFound => {
+            let entry = match addr.port() {
+                22 => host,
+                port => format!("[{}]:{}", host, port),
+            };
+            known_hosts.add(&entry, key, "burst", key_type.into())?;
+            known_hosts.write_file(path, ssh2::KnownHostFileKind::OpenSSH)?;
+            Ok(())
+        }
+        ssh2::CheckResult::Failure => Err("the known_hosts check failed".into()),
+    }
+}
+
+///The host keys listed in the console output of a machine, which cloud-init prints between
+///`-----BEGIN SSH HOST KEY KEYS-----` and `-----END SSH HOST KEY KEYS-----` on boot.
+pub fn keys_from_console(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .skip_while(|line| !line.contains("-----BEGIN SSH HOST KEY KEYS-----"))
+        .skip(1)
+        .take_while(|line| !line.contains("-----END SSH HOST KEY KEYS-----"))
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_keys_are_found_in_the_console_output() {
+        let output = "\
+[   12.3] cloud-init[1234]: Generating public/private ecdsa key pair.
+-----BEGIN SSH HOST KEY KEYS-----
+ecdsa-sha2-nistp256 AAAAE2VjZHNh root@ip-172-31-1-2
+ssh-ed25519 AAAAC3NzaC1lZDI1 root@ip-172-31-1-2
+-----END SSH HOST KEY KEYS-----
+[   13.0] cloud-init[1234]: Cloud-init finished
+";
+        assert_eq!(
+            keys_from_console(output),
+            ["AAAAE2VjZHNh", "AAAAC3NzaC1lZDI1"]
+        );
+        assert!(keys_from_console("still booting").is_empty());
+    }
+}
@@ -0,0 +1,261 @@
+use super::{ConnectOptions, Session};
+use crate::{BoxError, Error};
+use std::net::SocketAddr;
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::{Condvar, Mutex, MutexGuard};
+
+///The ssh connections to one machine, opened as commands need them.
+///
+///A command holds the lock of its connection until it exits, so commands running at the same
+///time each get a connection of their own, up to [`ConnectOptions::connections`]. A connection
+///found closed, after a reboot or a network failure, is dropped and replaced by a fresh one.
+pub(super) struct Pool<C = ssh2::Session> {
+    pub(super) addr: SocketAddr,
+    pub(super) options: ConnectOptions,
+    pub(super) private_key: PathBuf,
+    open: Box<dyn Fn() -> Result<C, Error> + Send + Sync>,
+    state: Mutex<State<C>>,
+    released: Condvar,
+}
+
+struct State<C> {
+    idle: Vec<C>,
+    open: usize,
+}
+
+impl Pool {
+    ///Open the first connection, so that a machine which can not be reached fails right away.
+    pub(super) fn connect(
+        addr: SocketAddr,
+        private_key: PathBuf,
+        options: ConnectOptions,
+    ) -> Result<Self, Error> {
+        let (key, connect) = (private_key.clone(), options.clone());
+        Self::open_with(addr, private_key, options, move || {
+            Session::open(addr, &key, &connect)
+        })
+    }
+}
+
+impl<C> Pool<C> {
+    ///Open the first connection with `open`, which opens every other one as well.
+    fn open_with(
+        addr: SocketAddr,
+        private_key: PathBuf,
+        options: ConnectOptions,
+        open: impl Fn() -> Result<C, Error> + Send + Sync + 'static,
+    ) -> Result<Self, Error> {
+        let first = open()?;
+        Ok(Self {
+            addr,
+            options,
+            private_key,
+            open: Box::new(open),
+            state: Mutex::new(State {
+                idle: vec![first],
+                open: 1,
+            }),
+            released: Condvar::new(),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State<C>> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn checkout(&self) -> Result<Connection<'_, C>, Error> {
+        let mut state = self.lock();
+        loop {
+            if let Some(session) = state.idle.pop() {
+                return Ok(Connection::new(self, session));
+            }
+            if state.open < self.options.connections.max(1) {
+                state.open += 1;
+                drop(state);
+                return match (self.open)() {
+                    Ok(session) => Ok(Connection::new(self, session)),
+                    Err(e) => {
+                        self.lock().open -= 1;
+                        self.released.notify_one();
+                        Err(e)
+                    }
+                };
+            }
+            state = self
+                .released
+                .wait(state)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+    }
+
+    ///Drop the idle connections, which are all gone once one of them is found closed or the
+    ///machine reboots.
+    pub(super) fn discard_idle(&self) {
+        let mut state = self.lock();
+        state.open -= state.idle.len();
+        state.idle.clear();
+        self.released.notify_all();
+    }
+
+    ///Start something on a connection, such as a channel or an sftp session, and finish it with
+    ///`run`. If it can not be started the connection is considered closed, and it is started
+    ///once more on a fresh connection.
+    pub(super) fn with<S, T, E: Into<BoxError>>(
+        &self,
+        start: impl Fn(&C) -> Result<S, E>,
+        run: impl FnOnce(S) -> Result<T, BoxError>,
+    ) -> Result<T, BoxError> {
+        let mut reconnected = false;
+        loop {
+            let mut connection = self.checkout()?;
+            match start(&connection) {
+                Ok(started) => return run(started),
+                Err(e) => {
+                    connection.closed = true;
+                    drop(connection);
+                    self.discard_idle();
+                    if reconnected {
+                        return Err(e.into());
+                    }
+                    reconnected = true;
+                }
+            }
+        }
+    }
+}
+
+///A connection checked out of the pool, which goes back to it when dropped.
+struct Connection<'a, C> {
+    pool: &'a Pool<C>,
+    session: Option<C>,
+    closed: bool,
+}
+
+impl<'a, C> Connection<'a, C> {
+    fn new(pool: &'a Pool<C>, session: C) -> Self {
+        Self {
+            pool,
+            session: Some(session),
+            closed: false,
+        }
+    }
+}
+
+impl<C> Deref for Connection<'_, C> {
+    type Target = C;
+
+    fn deref(&self) -> &C {
+        self.session
+            .as_ref()
+            .expect("the session is only taken on drop")
+    }
+}
+
+impl<C> Drop for Connection<'_, C> {
+    fn drop(&mut self) {
+        let session = self
+            .session
+            .take()
+            .expect("the session is only taken on drop");
+        let mut state = self.pool.lock();
+        if self.closed {
+            state.open -= 1;
+        } else {
+            state.idle.push(session);
+        }
+        self.pool.released.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    //A pool of connections which are the numbers of the order they were opened in, and how many
+    //were opened.
+    fn pool(connections: usize) -> (Pool<usize>, Arc<AtomicUsize>) {
+        let opened = Arc::new(AtomicUsize::new(0));
+        let count = opened.clone();
+        let options = ConnectOptions {
+            connections,
+            ..Default::default()
+        };
+        let addr = SocketAddr::from(([127, 0, 0, 1], 22));
+        let pool = Pool::open_with(addr, PathBuf::new(), options, move || {
+            Ok(count.fetch_add(1, Ordering::SeqCst) + 1)
+        })
+        .unwrap();
+        (pool, opened)
+    }
+
+    //Start something on a connection, which fails on the connections found closed.
+    fn start(closed: &Mutex<HashSet<usize>>) -> impl Fn(&usize) -> Result<usize, BoxError> {
+        move |connection| {
+            if closed.lock().unwrap().contains(connection) {
+                Err("the connection is closed".into())
+            } else {
+                Ok(*connection)
+            }
+        }
+    }
+
+    #[test]
+    fn closed_connections_are_opened_again() {
+        let (pool, opened) = pool(4);
+        let closed = Mutex::new(HashSet::from([1]));
+
+        let used = pool.with(start(&closed), Ok).unwrap();
+        assert_eq!(used, 2);
+        assert_eq!(opened.load(Ordering::SeqCst), 2);
+        assert_eq!(pool.with(start(&closed), Ok).unwrap(), 2);
+
+        // a connection which fails right after being opened is not opened a third time
+        closed.lock().unwrap().extend([2, 3]);
+        assert!(pool.with(start(&closed), Ok).is_err());
+        assert_eq!(opened.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn discarded_idle_connections_are_opened_again() {
+        let (pool, opened) = pool(4);
+        let closed = Mutex::new(HashSet::new());
+
+        assert_eq!(pool.with(start(&closed), Ok).unwrap(), 1);
+        pool.discard_idle();
+        assert_eq!(pool.with(start(&closed), Ok).unwrap(), 2);
+        assert_eq!(opened.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn commands_wait_for_a_connection_beyond_the_limit() {
+        let (pool, opened) = pool(2);
+        let closed = Mutex::new(HashSet::new());
+        let running = AtomicUsize::new(0);
+        let most = AtomicUsize::new(0);
+
+        std::thread::scope(|scope| {
+            for _ in 0..6 {
+                scope.spawn(|| {
+                    pool.with(start(&closed), |_| {
+                        let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                        most.fetch_max(now, Ordering::SeqCst);
+                        std::thread::sleep(Duration::from_millis(50));
+                        running.fetch_sub(1, Ordering::SeqCst);
+                        Ok(())
+                    })
+                    .unwrap();
+                });
+            }
+        });
+
+        assert_eq!(most.load(Ordering::SeqCst), 2);
+        assert_eq!(opened.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,214 @@
+use super::{ConnectOptions, Session};
+use crate::{BoxError, Error};
+use std::io::{BufRead, BufReader, Read};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+///The output stream a line of a background process was written to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    ///the standard output
+    Stdout,
+    ///the standard error
+    Stderr,
+}
+
+type OnLine = Arc<Mutex<dyn FnMut(Stream, &str) + Send>>;
+
+///A command running in the background on a machine, as started by [`Session::spawn`].
+///
+///Its output is handed line by line to a callback while it runs. Dropping the handle leaves the
+///process running; it goes away with the machine.
+pub struct Process {
+    command: String,
+    pid: u32,
+    session: Session,
+    reader: JoinHandle<Result<Option<i32>, BoxError>>,
+}
+
+impl Process {
+    pub(super) fn ssh(
+        session: &Session,
+        addr: SocketAddr,
+        private_key: &Path,
+        options: &ConnectOptions,
+        command: &str,
+        on_line: OnLine,
+    ) -> Result<Self, Error> {
+        // a channel blocks the whole ssh session while it waits for output, so every background
+        // process gets a connection of its own
+        let ssh = Session::open(addr, private_key, options)?;
+        let command_error = |source: BoxError| Error::Command {
+            command: command.to_string(),
+            source,
+        };
+        let mut channel = ssh.channel_session().map_err(|e| command_error(e.into()))?;
+        // the remote shell leads its own process group, which the command and its children stay
+        // in, so signalling the group reaches all of them
+        channel
+            .exec(&format!(
+                "echo $$; exec sh -c '{}'",
+                command.replace('\'', r"'\''")
+            ))
+            .map_err(|e| command_error(e.into()))?;
+        let pid = Self::read_pid(&mut channel).map_err(command_error)?;
+
+        let reader = std::thread::spawn(move || {
+            ssh.set_blocking(false);
+            let mut lines = [
+                (Stream::Stdout, channel.stream(0), Vec::new()),
+                (Stream::Stderr, channel.stderr(), Vec::new()),
+            ];
+            let mut buffer = [0; 4096];
+            loop {
+                let mut progressed = false;
+                for (stream, reader, pending) in &mut lines {
+                    match reader.read(&mut buffer) {
+                        Ok(0) => {}
+                        Ok(n) => {
+                            progressed = true;
+                            pending.extend_from_slice(&buffer[..n]);
+                            while let Some(end) = pending.iter().position(|&b| b == b'\n') {
+                                let line: Vec<u8> = pending.drain(..=end).collect();
+                                Self::emit(&on_line, *stream, &line[..end]);
+                            }
+                        }
+                        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+                if !progressed {
+                    if channel.eof() {
+                        break;
+                    }
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+            }
+            for (stream, _, pending) in &lines {
+                if !pending.is_empty() {
+                    Self::emit(&on_line, *stream, pending);
+                }
+            }
+
+            ssh.set_blocking(true);
+            channel.wait_close()?;
+            Ok(match channel.exit_signal()?.exit_signal {
+                Some(_) => None,
+                None => Some(channel.exit_status()?),
+            })
+        });
+
+        Ok(Self {
+            command: command.to_string(),
+            pid,
+            session: session.clone(),
+            reader,
+        })
+    }
+
+    pub(super) fn subprocess(
+        session: &Session,
+        dir: &Path,
+        command: &str,
+        on_line: OnLine,
+    ) -> Result<Self, Error> {
+        use std::os::unix::process::CommandExt;
+        use std::process::Stdio;
+
+        let mut child = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .current_dir(dir)
+            .process_group(0)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| Error::Command {
+                command: command.to_string(),
+                source: e.into(),
+            })?;
+        let pid = child.id();
+        let stdout = child.stdout.take().expect("the stdout is piped");
+        let stderr = child.stderr.take().expect("the stderr is piped");
+
+        let reader = std::thread::spawn(move || {
+            let forward = |stream, reader: Box<dyn Read + Send>| -> Result<(), BoxError> {
+                for line in BufReader::new(reader).split(b'\n') {
+                    Self::emit(&on_line, stream, &line?);
+                }
+                Ok(())
+            };
+            std::thread::scope(|scope| {
+                let errors = scope.spawn(|| forward(Stream::Stderr, Box::new(stderr)));
+                forward(Stream::Stdout, Box::new(stdout))?;
+                errors.join().expect("the stderr reader does not panic")
+            })?;
+            Ok(child.wait()?.code())
+        });
+
+        Ok(Self {
+            command: command.to_string(),
+            pid,
+            session: session.clone(),
+            reader,
+        })
+    }
+
+    fn read_pid(channel: &mut ssh2::Channel) -> Result<u32, BoxError> {
+        let mut line = Vec::new();
+        let mut byte = [0];
+        while channel.read(&mut byte)? == 1 && byte[0] != b'\n' {
+            line.push(byte[0]);
+        }
+        Ok(String::from_utf8_lossy(&line).trim().parse()?)
+    }
+
+    fn emit(on_line: &OnLine, stream: Stream, line: &[u8]) {
+        let mut on_line = on_line
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        on_line(stream, &String::from_utf8_lossy(line));
+    }
+
+    ///The process id, which is also the id of the process group the command runs in.
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    ///Whether the process has exited and all of its output has been handed out.
+    pub fn is_finished(&self) -> bool {
+        self.reader.is_finished()
+    }
+
+    ///Send a signal, such as `TERM`, `INT` or `KILL`, to the process and its children.
+    pub fn signal(&self, signal: &str) -> Result<(), Error> {
+        self.session
+            .exec_checked(&format!("kill -s {} -- -{}", signal, self.pid))?;
+        Ok(())
+    }
+
+    ///Wait for the process to exit, and return its exit status, `None` if it was killed by a
+    ///signal.
+    pub fn wait(self) -> Result<Option<i32>, Error> {
+        let command = self.command;
+        self.reader
+            .join()
+            .unwrap_or_else(|_| Err("the output reader panicked".into()))
+            .map_err(|source| Error::Command { command, source })
+    }
+
+    ///Terminate the process with `TERM` and wait for it to exit.
+    pub fn kill(self) -> Result<Option<i32>, Error> {
+        if let Err(e) = self.signal("TERM") {
+            // there is nothing left to signal if it exited on its own
+            if !self.is_finished() {
+                return Err(e);
+            }
+        }
+        self.wait()
+    }
+}
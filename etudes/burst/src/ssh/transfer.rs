@@ -0,0 +1,78 @@
+use crate::BoxError;
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+fn mode(metadata: &fs::Metadata) -> u32 {
+    metadata.permissions().mode() & 0o7777
+}
+
+///Copy a local file or directory to the machine over sftp, keeping the permissions.
+pub(super) fn upload(sftp: &ssh2::Sftp, local: &Path, remote: &Path) -> Result<(), BoxError> {
+    let metadata = fs::metadata(local)?;
+    if metadata.is_dir() {
+        // the directory stays writable until its children are in, and gets its own mode after
+        if sftp.stat(remote).is_err() {
+            sftp.mkdir(remote, 0o700)?;
+        }
+        for entry in fs::read_dir(local)? {
+            let entry = entry?;
+            upload(sftp, &entry.path(), &remote.join(entry.file_name()))?;
+        }
+    } else {
+        let flags = ssh2::OpenFlags::WRITE | ssh2::OpenFlags::CREATE | ssh2::OpenFlags::TRUNCATE;
+        let mut file =
+            sftp.open_mode(remote, flags, mode(&metadata) as i32, ssh2::OpenType::File)?;
+        io::copy(&mut fs::File::open(local)?, &mut file)?;
+    }
+    // the mode given on creation is filtered by the umask, and an existing file keeps its own
+    sftp.setstat(
+        remote,
+        ssh2::FileStat {
+            size: None,
+            uid: None,
+            gid: None,
+            perm: Some(mode(&metadata)),
+            atime: None,
+            mtime: None,
+        },
+    )?;
+    Ok(())
+}
+
+///Copy a file or directory of the machine to the local disk over sftp, keeping the permissions.
+pub(super) fn download(sftp: &ssh2::Sftp, remote: &Path, local: &Path) -> Result<(), BoxError> {
+    let stat = sftp.stat(remote)?;
+    if stat.is_dir() {
+        fs::create_dir_all(local)?;
+        for (path, _) in sftp.readdir(remote)? {
+            let name = path.file_name().ok_or("the directory entry has no name")?;
+            download(sftp, &path, &local.join(name))?;
+        }
+    } else {
+        io::copy(&mut sftp.open(remote)?, &mut fs::File::create(local)?)?;
+    }
+    if let Some(perm) = stat.perm {
+        fs::set_permissions(local, fs::Permissions::from_mode(perm & 0o7777))?;
+    }
+    Ok(())
+}
+
+///Copy a file or directory on the local disk, keeping the permissions, for sessions which run
+///their commands as local subprocesses.
+pub(super) fn copy(from: &Path, to: &Path) -> Result<(), BoxError> {
+    let metadata = fs::metadata(from)?;
+    if metadata.is_dir() {
+        fs::create_dir_all(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        fs::set_permissions(to, metadata.permissions())?;
+    } else {
+        // the permissions are copied along with the content
+        fs::copy(from, to)?;
+    }
+    Ok(())
+}
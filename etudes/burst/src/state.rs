@@ -0,0 +1,168 @@
+use crate::reaper;
+use crate::teardown::Resources;
+use crate::{Error, Fulfillment, MachineInfo};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+///A cluster left running by [`Lifecycle::KeepAlive`](crate::Lifecycle::KeepAlive), as stored in
+///its state file. The private key and the known hosts are stored next to it, see [`key_file`]
+///and [`known_hosts`].
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct ClusterState {
+    ///the id of the run which launched the cluster
+    pub(crate) run_id: String,
+    ///when the machines shut themselves down, in seconds since the unix epoch
+    pub(crate) expires_at: u64,
+    ///the machines of each setup
+    pub(crate) machines: HashMap<String, Vec<MachineInfo>>,
+    ///how each setup was fulfilled
+    pub(crate) fulfillment: HashMap<String, Fulfillment>,
+    ///everything to release once the cluster is destroyed
+    pub(crate) resources: Resources,
+}
+
+impl ClusterState {
+    ///The state of a cluster being launched, which expires after `duration`. The run id of a
+    ///cluster `kept_alive` holds its expiry, so that the reaper leaves it alone until then.
+    pub(crate) fn new(duration: Duration, kept_alive: bool) -> Self {
+        let expires_at = (SystemTime::now() + duration)
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        Self {
+            run_id: reaper::new_run_id(kept_alive.then_some(expires_at)),
+            expires_at,
+            machines: HashMap::new(),
+            fulfillment: HashMap::new(),
+            resources: Resources::default(),
+        }
+    }
+
+    pub(crate) fn load(path: &Path) -> Result<Self, Error> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| Error::io(format!("read the cluster state file {}", path.display()), e))?;
+        serde_json::from_str(&json).map_err(|e| Error::ClusterState {
+            path: path.to_path_buf(),
+            reason: e.to_string(),
+        })
+    }
+
+    pub(crate) fn save(&self, path: &Path) -> Result<(), Error> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| Error::ClusterState {
+            path: path.to_path_buf(),
+            reason: e.to_string(),
+        })?;
+        std::fs::write(path, json).map_err(|e| {
+            Error::io(
+                format!("write the cluster state file {}", path.display()),
+                e,
+            )
+        })
+    }
+
+    ///The instant the machines shut themselves down at, or an error if it has passed.
+    pub(crate) fn deadline(&self, path: &Path) -> Result<Instant, Error> {
+        let expires_at = UNIX_EPOCH + Duration::from_secs(self.expires_at);
+        match expires_at.duration_since(SystemTime::now()) {
+            Ok(left) => Ok(Instant::now() + left),
+            Err(_) => Err(Error::ClusterState {
+                path: path.to_path_buf(),
+                reason: "the machines have reached their maximum duration and shut themselves \
+                         down, destroy the cluster to release the rest"
+                    .to_string(),
+            }),
+        }
+    }
+}
+
+///The private key of the cluster described by the given state file.
+pub(crate) fn key_file(path: &Path) -> PathBuf {
+    path.with_extension("pem")
+}
+
+///The known hosts of the cluster described by the given state file.
+pub(crate) fn known_hosts(path: &Path) -> PathBuf {
+    path.with_extension("known_hosts")
+}
+
+///Remove the state file and the files stored next to it, those which exist.
+pub(crate) fn remove(path: &Path) -> Result<(), Error> {
+    for file in [path.to_path_buf(), key_file(path), known_hosts(path)] {
+        match std::fs::remove_file(&file) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                return Err(Error::io(format!("remove {}", file.display()), e));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::Fake;
+    use crate::testing::assert_torn_down;
+    use crate::{BurstBuilder, Lifecycle, MachineSetup};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn kept_alive_cluster_is_attached_to_and_destroyed() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = dir.path().join("bench.json");
+        let setups = Arc::new(AtomicUsize::new(0));
+        let builder = |lifecycle: Lifecycle| {
+            let mut builder = BurstBuilder::default();
+            builder.set_lifecycle(lifecycle);
+            let setups = setups.clone();
+            builder.add_setup(
+                "server".to_string(),
+                2,
+                MachineSetup::new("t2.micro", "ami-fake", move |ssh| {
+                    setups.fetch_add(1, Ordering::SeqCst);
+                    ssh.cmd("echo set up > role")?;
+                    Ok(())
+                }),
+            );
+            builder
+        };
+        let fake = Fake::new();
+
+        let rt = Runtime::new().unwrap();
+        rt.block_on(
+            builder(Lifecycle::KeepAlive(state.clone())).run_with(&fake, |vms| {
+                vms["server"][0].run("echo first > marker")?;
+                Ok(())
+            }),
+        )
+        .unwrap();
+        assert_eq!(fake.running_instances().len(), 2);
+        assert!(state.exists() && key_file(&state).exists());
+
+        // a second cluster is not launched over the first one
+        let again =
+            rt.block_on(builder(Lifecycle::KeepAlive(state.clone())).run_with(&fake, |_| Ok(())));
+        assert!(matches!(again, Err(Error::ClusterState { .. })));
+
+        rt.block_on(
+            builder(Lifecycle::Attach(state.clone())).run_with(&fake, |vms| {
+                assert_eq!(vms["server"].len(), 2);
+                assert_eq!(vms["server"][0].run("cat marker")?, "first\n");
+                assert_eq!(vms["server"][1].run("cat role")?, "set up\n");
+                Ok(())
+            }),
+        )
+        .unwrap();
+        assert_eq!(setups.load(Ordering::SeqCst), 2);
+        assert_eq!(fake.running_instances().len(), 2);
+
+        rt.block_on(BurstBuilder::default().destroy_with(&fake, &state))
+            .unwrap();
+        assert_torn_down(&fake);
+        assert!(!state.exists() && !key_file(&state).exists());
+    }
+}
@@ -0,0 +1,233 @@
+use crate::{Error, Report};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+///The combinations of machine counts and parameters a benchmark is run with by
+///[`BurstBuilder::sweep`](crate::BurstBuilder::sweep), on a cluster launched once for the largest
+///counts.
+///
+///Every combination of the values of the dimensions is an [`Iteration`]. The machine counts vary
+///more slowly than the parameters, whatever order they were added in, so every value of the
+///parameters is run with a number of machines before the next one. Among the machine counts,
+///and among the parameters, the dimension added first varies the slowest.
+///```rust
+/// # use burst::Sweep;
+/// let sweep = Sweep::new()
+///     .with_parameter("payload", ["1k", "1m"])
+///     .with_machines("client", [1, 2, 4]);
+/// let iterations = sweep.iterations();
+/// assert_eq!(iterations.len(), 6);
+/// assert_eq!(iterations[1].machines["client"], 1);
+/// assert_eq!(iterations[1].parameter("payload"), Some("1m"));
+///```
+#[derive(Debug, Clone, Default)]
+pub struct Sweep {
+    machines: Vec<(String, Vec<usize>)>,
+    parameters: Vec<(String, Vec<String>)>,
+}
+
+impl Sweep {
+    ///A sweep with a single iteration, on all the machines.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///Run the iterations with each of the given numbers of machines of the setup with the given
+    ///name. The setup is launched with the largest of them, whatever number was passed to
+    ///`add_setup`.
+    pub fn with_machines(mut self, setup: &str, counts: impl IntoIterator<Item = usize>) -> Self {
+        self.machines.retain(|(name, _)| name != setup);
+        self.machines
+            .push((setup.to_string(), counts.into_iter().collect()));
+        self
+    }
+
+    ///Run the iterations with each of the given values of the parameter with the given name,
+    ///which the script reads through [`Iteration::parameter`].
+    pub fn with_parameter<V: ToString>(
+        mut self,
+        name: &str,
+        values: impl IntoIterator<Item = V>,
+    ) -> Self {
+        self.parameters.retain(|(parameter, _)| parameter != name);
+        self.parameters.push((
+            name.to_string(),
+            values.into_iter().map(|value| value.to_string()).collect(),
+        ));
+        self
+    }
+
+    ///Every combination of the machine counts and the parameters, in the order they are run.
+    pub fn iterations(&self) -> Vec<Iteration> {
+        let mut iterations = vec![Iteration::default()];
+        for (setup, counts) in &self.machines {
+            iterations = iterations
+                .iter()
+                .flat_map(|iteration| {
+                    counts.iter().map(move |count| {
+                        let mut iteration = iteration.clone();
+                        iteration.machines.insert(setup.clone(), *count);
+                        iteration
+                    })
+                })
+                .collect();
+        }
+        for (name, values) in &self.parameters {
+            iterations = iterations
+                .iter()
+                .flat_map(|iteration| {
+                    values.iter().map(move |value| {
+                        let mut iteration = iteration.clone();
+                        iteration.parameters.insert(name.clone(), value.clone());
+                        iteration
+                    })
+                })
+                .collect();
+        }
+        for (index, iteration) in iterations.iter_mut().enumerate() {
+            iteration.index = index;
+        }
+        iterations
+    }
+
+    ///The largest number of machines of each setup the sweep varies the machines of.
+    pub(crate) fn largest_counts(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.machines.iter().map(|(setup, counts)| {
+            let largest = counts.iter().copied().max().unwrap_or(0);
+            (setup.as_str(), largest)
+        })
+    }
+}
+
+///One combination of the machine counts and the parameters of a [`Sweep`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Iteration {
+    ///the position of the iteration in the sweep
+    pub index: usize,
+    ///the number of machines of each setup the sweep varies the machines of; the script is
+    ///handed the first ones, and all the machines of the other setups
+    pub machines: BTreeMap<String, usize>,
+    ///the value of each parameter
+    pub parameters: BTreeMap<String, String>,
+}
+
+impl Iteration {
+    ///The value of the parameter with the given name.
+    pub fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters.get(name).map(String::as_str)
+    }
+}
+
+///How an iteration of a sweep went. The iterations are run whether the earlier ones failed or
+///not.
+#[derive(Debug)]
+pub struct IterationResult {
+    ///the iteration
+    pub iteration: Iteration,
+    ///what the script returned, or why it could not be run with the machines of the iteration
+    pub result: Result<(), Error>,
+    ///the report of the iteration, `None` if the script was not run
+    pub report: Option<Report>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::Fake;
+    use crate::testing::{assert_torn_down, builder};
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn iterations_cover_every_combination() {
+        let sweep = Sweep::new()
+            .with_machines("client", [4, 1])
+            .with_machines("server", [1, 2])
+            .with_parameter("payload", [10, 20])
+            .with_machines("client", [1, 3]);
+        let all = sweep.iterations();
+        let iterations: Vec<(usize, usize, Option<&str>)> = all
+            .iter()
+            .map(|iteration| {
+                (
+                    iteration.machines["server"],
+                    iteration.machines["client"],
+                    iteration.parameter("payload"),
+                )
+            })
+            .collect();
+        assert_eq!(
+            iterations,
+            [
+                (1, 1, Some("10")),
+                (1, 1, Some("20")),
+                (1, 3, Some("10")),
+                (1, 3, Some("20")),
+                (2, 1, Some("10")),
+                (2, 1, Some("20")),
+                (2, 3, Some("10")),
+                (2, 3, Some("20")),
+            ]
+        );
+        let mut largest: Vec<_> = sweep.largest_counts().collect();
+        largest.sort();
+        assert_eq!(largest, [("client", 3), ("server", 2)]);
+        assert_eq!(Sweep::new().iterations(), [Iteration::default()]);
+    }
+
+    #[test]
+    fn sweep_runs_every_iteration_on_one_cluster() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut builder = builder(&[("server", 1), ("client", 1)]);
+        builder.set_report_dir(dir.path());
+        let sweep = Sweep::new()
+            .with_machines("client", [1, 3])
+            .with_parameter("payload", ["1k", "1m"]);
+        let fake = Fake::new();
+
+        let rt = Runtime::new().unwrap();
+        let results = rt
+            .block_on(builder.sweep_with(&fake, &sweep, |iteration, vms| {
+                assert_eq!(vms["server"].len(), 1);
+                assert_eq!(vms["client"].len(), iteration.machines["client"]);
+                assert_eq!(fake.running_instances().len(), 4);
+                if iteration.parameter("payload") == Some("1m") && vms["client"].len() == 3 {
+                    panic!("out of memory");
+                }
+                vms.record("clients", vms["client"].len() as f64);
+                Ok(())
+            }))
+            .unwrap();
+        assert_torn_down(&fake);
+
+        let failed: Vec<usize> = results
+            .iter()
+            .filter(|result| result.result.is_err())
+            .map(|result| result.iteration.index)
+            .collect();
+        assert_eq!(failed, [3]);
+        assert!(
+            matches!(&results[3].result, Err(Error::Script(e)) if e.to_string().contains("out of memory"))
+        );
+        let report = results[2].report.as_ref().unwrap();
+        assert_eq!(report.setups["client"].machines, 3);
+        assert_eq!(report.metrics.len(), 1);
+        assert_eq!(report.metrics[0].value, 3.0);
+        assert!(
+            dir.path()
+                .join(format!("burst_{}_3.csv", report.run_id))
+                .exists()
+        );
+
+        // the sweep leaves the setups as they were added
+        rt.block_on(builder.run_with(&fake, |vms| {
+            assert_eq!(vms["client"].len(), 1);
+            assert_eq!(fake.running_instances().len(), 2);
+            Ok(())
+        }))
+        .unwrap();
+
+        let too_many = Sweep::new().with_machines("database", [1]);
+        let result = rt.block_on(builder.sweep_with(&fake, &too_many, |_, _| Ok(())));
+        assert!(matches!(result, Err(Error::Sweep(_))));
+    }
+}
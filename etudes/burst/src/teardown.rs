@@ -0,0 +1,334 @@
+use crate::Error;
+use crate::provider::Provider;
+use serde::{Deserialize, Serialize};
+use slog::Logger;
+
+///The resources created for a burst, as tracked by its [`Teardown`] and stored in the state file
+///of a cluster which is left running.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Resources {
+    spot_requests: Vec<String>,
+    instances: Vec<String>,
+    security_group: Option<String>,
+    key_pair: Option<String>,
+    placement_group: Option<String>,
+    subnet: Option<String>,
+    internet_gateway: Option<(String, Option<String>)>,
+    vpc: Option<String>,
+}
+
+impl Resources {
+    fn is_empty(&self) -> bool {
+        self.spot_requests.is_empty()
+            && self.instances.is_empty()
+            && self.security_group.is_none()
+            && self.key_pair.is_none()
+            && self.placement_group.is_none()
+            && self.subnet.is_none()
+            && self.internet_gateway.is_none()
+            && self.vpc.is_none()
+    }
+}
+
+///Owns every resource created for a burst and releases them when dropped.
+///
+///`run` registers each resource as soon as the provider has created it and calls
+///[`Teardown::release`] once it is done, whether it succeeded or not. If the run is unwinding
+///instead (a panic in a setup closure or in the user script, or the run future being dropped),
+///`Drop` releases whatever is left on a fresh runtime, since it can not await, through a
+///[detached](Provider::detached) provider.
+pub(crate) struct Teardown<'a, P: Provider> {
+    provider: &'a P,
+    logger: Logger,
+    resources: Resources,
+}
+
+impl<'a, P: Provider> Teardown<'a, P> {
+    pub(crate) fn new(provider: &'a P, logger: Logger) -> Self {
+        Self {
+            provider,
+            logger,
+            resources: Resources::default(),
+        }
+    }
+
+    pub(crate) fn vpc(&mut self, vpc_id: &str) {
+        self.resources.vpc = Some(vpc_id.to_string());
+    }
+
+    pub(crate) fn internet_gateway(&mut self, gateway_id: &str, vpc_id: Option<&str>) {
+        self.resources.internet_gateway =
+            Some((gateway_id.to_string(), vpc_id.map(str::to_string)));
+    }
+
+    pub(crate) fn subnet(&mut self, subnet_id: &str) {
+        self.resources.subnet = Some(subnet_id.to_string());
+    }
+
+    pub(crate) fn placement_group(&mut self, name: &str) {
+        self.resources.placement_group = Some(name.to_string());
+    }
+
+    pub(crate) fn security_group(&mut self, group_id: &str) {
+        self.resources.security_group = Some(group_id.to_string());
+    }
+
+    pub(crate) fn key_pair(&mut self, key_name: &str) {
+        self.resources.key_pair = Some(key_name.to_string());
+    }
+
+    pub(crate) fn spot_requests(&mut self, ids: &[String]) {
+        self.resources.spot_requests.extend_from_slice(ids);
+    }
+
+    pub(crate) fn instances(&mut self, ids: &[String]) {
+        for id in ids {
+            if !self.resources.instances.contains(id) {
+                self.resources.instances.push(id.clone());
+            }
+        }
+    }
+
+    ///Take over the resources of a cluster which was left running, so they get released along
+    ///with the tracked ones.
+    pub(crate) fn adopt(&mut self, resources: Resources) {
+        let Resources {
+            spot_requests,
+            instances,
+            security_group,
+            key_pair,
+            placement_group,
+            subnet,
+            internet_gateway,
+            vpc,
+        } = resources;
+        self.spot_requests(&spot_requests);
+        self.instances(&instances);
+        let tracked = &mut self.resources;
+        tracked.security_group = security_group.or(tracked.security_group.take());
+        tracked.key_pair = key_pair.or(tracked.key_pair.take());
+        tracked.placement_group = placement_group.or(tracked.placement_group.take());
+        tracked.subnet = subnet.or(tracked.subnet.take());
+        tracked.internet_gateway = internet_gateway.or(tracked.internet_gateway.take());
+        tracked.vpc = vpc.or(tracked.vpc.take());
+    }
+
+    ///Stop tracking the resources, which are left running, and return them.
+    pub(crate) fn keep(&mut self) -> Resources {
+        std::mem::take(&mut self.resources)
+    }
+
+    ///Cancel the spot requests which are still tracked, so no further instances get launched.
+    pub(crate) async fn cancel_spot_requests(&mut self) -> Result<(), Error> {
+        let ids = std::mem::take(&mut self.resources.spot_requests);
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        debug!(self.logger, "cancelling the spot requests"; "requests"=>?ids);
+        self.provider.cancel_spot_instance_requests(&ids).await?;
+
+        // a request may have been fulfilled between the last describe and the cancellation
+        let requests = self.provider.describe_spot_instance_requests(&ids).await?;
+        let launched: Vec<String> = requests.into_iter().filter_map(|r| r.instance_id).collect();
+        self.instances(&launched);
+        Ok(())
+    }
+
+    ///Release every tracked resource. Each release is attempted even if an earlier one failed,
+    ///and the first failure is returned. Resources still in use by the instances just terminated
+    ///are retried by the provider, as its retry policy says.
+    pub(crate) async fn release(&mut self) -> Result<(), Error> {
+        let mut first_error = None;
+        let logger = self.logger.clone();
+        let mut released = |what: &str, result: Result<(), Error>| {
+            if let Err(e) = result {
+                error!(logger, "releasing the {} failed", what; "error"=>?e);
+                first_error.get_or_insert(e);
+            }
+        };
+
+        released("spot requests", self.cancel_spot_requests().await);
+
+        let instances = std::mem::take(&mut self.resources.instances);
+        if !instances.is_empty() {
+            debug!(self.logger, "terminating the instances"; "instances"=>?instances);
+            released(
+                "instances",
+                self.provider.terminate_instances(&instances).await,
+            );
+        }
+
+        if let Some(group_id) = self.resources.security_group.take() {
+            debug!(self.logger, "deleting the security group";"group_id"=>&group_id);
+            released(
+                "security group",
+                self.provider.delete_security_group(&group_id).await,
+            );
+        }
+
+        if let Some(key_name) = self.resources.key_pair.take() {
+            debug!(self.logger, "deleting the key-pair";"key_name"=>&key_name);
+            released("key-pair", self.provider.delete_key_pair(&key_name).await);
+        }
+
+        if let Some(name) = self.resources.placement_group.take() {
+            debug!(self.logger, "deleting the placement group";"name"=>&name);
+            released(
+                "placement group",
+                self.provider.delete_placement_group(&name).await,
+            );
+        }
+
+        // the vpc goes last, once nothing is left in it
+        if let Some(subnet_id) = self.resources.subnet.take() {
+            debug!(self.logger, "deleting the subnet";"subnet_id"=>&subnet_id);
+            released("subnet", self.provider.delete_subnet(&subnet_id).await);
+        }
+
+        if let Some((gateway_id, vpc_id)) = self.resources.internet_gateway.take() {
+            debug!(self.logger, "deleting the internet gateway";"gateway_id"=>&gateway_id);
+            released(
+                "internet gateway",
+                self.provider
+                    .delete_internet_gateway(&gateway_id, vpc_id.as_deref())
+                    .await,
+            );
+        }
+
+        if let Some(vpc_id) = self.resources.vpc.take() {
+            debug!(self.logger, "deleting the vpc";"vpc_id"=>&vpc_id);
+            released("vpc", self.provider.delete_vpc(&vpc_id).await);
+        }
+
+        first_error.map(Err).unwrap_or(Ok(()))
+    }
+}
+
+impl<P: Provider> Drop for Teardown<'_, P> {
+    fn drop(&mut self) {
+        if self.resources.is_empty() {
+            return;
+        }
+
+        warn!(
+            self.logger,
+            "the burst did not finish, releasing the remaining resources"
+        );
+        // a runtime can not be started from within another one, and the current thread is likely
+        // inside the caller's runtime, so the release is driven from a separate thread
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                let rt = match tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                {
+                    Ok(rt) => rt,
+                    Err(e) => {
+                        crit!(self.logger, "failed to start a runtime for the teardown"; "error"=>?e);
+                        return;
+                    }
+                };
+                // the caller's runtime is blocked on this drop, so the connections it opened are
+                // not driven any more and the provider is detached from them
+                let detached = self.provider.detached().unwrap_or_else(|e| {
+                    error!(self.logger, "failed to detach the provider for the teardown"; "error"=>%e);
+                    None
+                });
+                let mut teardown = Teardown {
+                    provider: detached.as_ref().unwrap_or(self.provider),
+                    logger: self.logger.clone(),
+                    resources: std::mem::take(&mut self.resources),
+                };
+                if let Err(e) = rt.block_on(teardown.release()) {
+                    crit!(self.logger, "the teardown failed, resources may be left behind"; "error"=>?e);
+                }
+            });
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::provider::Fake;
+    use crate::testing::{builder, run_torn_down};
+    use crate::{Error, Fulfillment, MachineSetup};
+
+    #[test]
+    fn failed_spot_requests_are_torn_down() {
+        let mut builder = builder(&[]);
+        builder.add_setup(
+            "server".to_string(),
+            2,
+            MachineSetup::new("p3.16xlarge", "ami-fake", |_| Ok(())),
+        );
+        let fake = Fake::new().with_open_polls(3).reject_spot("p3.16xlarge");
+
+        let error = run_torn_down(
+            &fake,
+            builder.run_with(&fake, |_| panic!("the script must not run")),
+        )
+        .unwrap_err();
+
+        let Error::Shortfall(shortfall) = error else {
+            panic!("expected a shortfall, got {}", error);
+        };
+        assert_eq!(shortfall.name, "server");
+        assert_eq!(
+            shortfall.fulfillment,
+            Fulfillment {
+                requested: 2,
+                obtained: 0,
+                retries: 0
+            }
+        );
+    }
+
+    #[test]
+    fn failed_setup_is_torn_down() {
+        let mut builder = builder(&[]);
+        builder.add_setup(
+            "server".to_string(),
+            2,
+            MachineSetup::new("t2.micro", "ami-fake", |ssh| {
+                ssh.cmd("true")?;
+                Err("yum install failed".into())
+            }),
+        );
+        let fake = Fake::new();
+
+        let error = run_torn_down(
+            &fake,
+            builder.run_with(&fake, |_| panic!("the script must not run")),
+        )
+        .unwrap_err();
+
+        let Error::Setup(failures) = error else {
+            panic!("expected a setup failure, got {}", error);
+        };
+        assert_eq!(failures.len(), 2);
+        for failure in &failures {
+            assert_eq!(failure.name, "server");
+            assert!(failure.public_ip.starts_with("203.0.113."));
+            assert!(
+                matches!(&failure.error, Error::Closure(e) if e.to_string() == "yum install failed")
+            );
+        }
+    }
+
+    #[test]
+    fn panicking_script_is_torn_down() {
+        let mut builder = builder(&[("server", 1)]);
+        let fake = Fake::new().with_open_polls(1);
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            run_torn_down(
+                &fake,
+                builder.run_with(&fake, |_| panic!("benchmark crashed")),
+            )
+        }));
+
+        assert!(result.is_err());
+        crate::testing::assert_torn_down(&fake);
+    }
+}
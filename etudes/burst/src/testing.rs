@@ -0,0 +1,37 @@
+//!What the tests of whole bursts on the [`Fake`] provider share.
+
+use crate::provider::Fake;
+use crate::{BurstBuilder, MachineSetup};
+use std::future::Future;
+use tokio::runtime::Runtime;
+
+///A setup of `t2.micro` machines which need no setting up.
+pub(crate) fn idle() -> MachineSetup {
+    MachineSetup::new("t2.micro", "ami-fake", |_| Ok(()))
+}
+
+///A builder with the given numbers of [`idle`] machines, by setup name.
+pub(crate) fn builder(setups: &[(&str, i64)]) -> BurstBuilder {
+    let mut builder = BurstBuilder::default();
+    for (name, number) in setups {
+        builder.add_setup(name.to_string(), *number, idle());
+    }
+    builder
+}
+
+///Run a burst on a runtime of its own, and check that it left nothing behind on the fake
+///provider, whatever it returned.
+pub(crate) fn run_torn_down<T>(fake: &Fake, burst: impl Future<Output = T>) -> T {
+    let result = Runtime::new().unwrap().block_on(burst);
+    assert_torn_down(fake);
+    result
+}
+
+///Check that nothing a burst creates is left on the fake provider.
+pub(crate) fn assert_torn_down(fake: &Fake) {
+    assert!(fake.running_instances().is_empty());
+    assert!(fake.live_spot_requests().is_empty());
+    assert!(fake.security_groups().is_empty());
+    assert!(fake.key_pairs().is_empty());
+    assert!(fake.network_resources().is_empty());
+}
@@ -0,0 +1,73 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+///What is known about a machine of the burst, without a session to it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MachineInfo {
+    ///the name of the setup the machine belongs to
+    pub name: String,
+    ///the position of the machine among the machines of its setup, from 0
+    pub index: usize,
+    ///the instance id
+    pub instance_id: String,
+    ///the instance type
+    pub instance_type: String,
+    ///the availability zone, if the provider reports one
+    pub availability_zone: Option<String>,
+    ///the private ip, which the other machines reach it with
+    pub private_ip: String,
+    ///the public host name
+    pub public_dns: String,
+    ///the public ip
+    pub public_ip: String,
+}
+
+///The machines of the whole burst as seen from one of them, as handed to the setup closures
+///made with [`MachineSetup::new_with_topology`](crate::MachineSetup::new_with_topology).
+///
+///Every machine is up with its addresses by the time the setups run, so a client can be
+///pointed at its server while it is being set up.
+#[derive(Debug, Clone)]
+pub struct Topology {
+    machines: Arc<HashMap<String, Vec<MachineInfo>>>,
+    name: String,
+    index: usize,
+}
+
+impl Topology {
+    pub(crate) fn new(
+        machines: Arc<HashMap<String, Vec<MachineInfo>>>,
+        name: &str,
+        index: usize,
+    ) -> Self {
+        Self {
+            machines,
+            name: name.to_string(),
+            index,
+        }
+    }
+
+    ///The machine being set up.
+    pub fn this(&self) -> &MachineInfo {
+        &self.machines[&self.name][self.index]
+    }
+
+    ///The machines of the setup with the given name, none if there is no such setup.
+    pub fn machines(&self, name: &str) -> &[MachineInfo] {
+        self.machines.get(name).map_or(&[], Vec::as_slice)
+    }
+
+    ///The private ips of the machines of the setup with the given name.
+    pub fn private_ips(&self, name: &str) -> Vec<&str> {
+        self.machines(name)
+            .iter()
+            .map(|machine| machine.private_ip.as_str())
+            .collect()
+    }
+
+    ///The names of the setups.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.machines.keys().map(String::as_str)
+    }
+}
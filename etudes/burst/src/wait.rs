@@ -0,0 +1,96 @@
+use std::fmt;
+use std::time::Duration;
+
+///How long to sleep between two polls of the provider while waiting for it.
+///
+///The first sleep is `initial`, and every following one is `factor` times longer than the
+///previous, up to `max`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Backoff {
+    ///the first delay
+    pub initial: Duration,
+    ///the longest delay
+    pub max: Duration,
+    ///how much longer every delay is than the one before
+    pub factor: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(200),
+            max: Duration::from_secs(10),
+            factor: 2,
+        }
+    }
+}
+
+impl Backoff {
+    ///The successive delays.
+    pub fn delays(&self) -> impl Iterator<Item = Duration> {
+        let (max, factor) = (self.max, self.factor);
+        std::iter::successors(Some(self.initial.min(max)), move |delay| {
+            Some(delay.saturating_mul(factor).min(max))
+        })
+    }
+}
+
+///A phase of `run` which waits for the provider.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WaitPhase {
+    ///waiting for the spot requests to be fulfilled
+    SpotRequests,
+    ///waiting for the instances to be up with their addresses
+    Instances,
+    ///waiting for the instances to list their ssh host keys in their console output
+    HostKeys,
+}
+
+impl fmt::Display for WaitPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WaitPhase::SpotRequests => write!(f, "spot requests"),
+            WaitPhase::Instances => write!(f, "instances"),
+            WaitPhase::HostKeys => write!(f, "host keys"),
+        }
+    }
+}
+
+///The error `run` fails with when a wait phase does not finish in time.
+#[derive(Debug)]
+pub struct Timeout {
+    ///the phase which timed out
+    pub phase: WaitPhase,
+    ///how long the phase was allowed to take
+    pub timeout: Duration,
+    ///the ids of the spot requests or instances which were not ready
+    pub pending: Vec<String>,
+}
+
+impl fmt::Display for Timeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the {} did not become ready within {:?}: {:?}",
+            self.phase, self.timeout, self.pending
+        )
+    }
+}
+
+impl std::error::Error for Timeout {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delays_grow_up_to_the_maximum() {
+        let backoff = Backoff {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(5),
+            factor: 2,
+        };
+        let delays: Vec<u64> = backoff.delays().take(5).map(|d| d.as_secs()).collect();
+        assert_eq!(delays, [1, 2, 4, 5, 5]);
+    }
+}
@@ -0,0 +1,240 @@
+use crate::Error;
+use crate::provider::Provider;
+use slog::Logger;
+use std::future::Future;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use tokio::time;
+
+///Run `work` while a watchdog thread waits for the deadline. If the deadline passes first, the
+///watchdog terminates the instances, which makes every following command on them fail so the
+///work can wind down, and the second value of the result is `true`.
+///
+///The work itself is not interrupted: a closure which never touches the machines again keeps
+///running until it returns on its own.
+pub(crate) fn run_until<P, T>(
+    provider: &P,
+    logger: &Logger,
+    instance_ids: &[String],
+    deadline: Instant,
+    work: impl FnOnce() -> T,
+) -> (T, bool)
+where
+    P: Provider,
+{
+    let (finished, watchdog) = mpsc::channel::<()>();
+    std::thread::scope(|scope| {
+        let expired = scope.spawn(move || {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match watchdog.recv_timeout(timeout) {
+                Err(RecvTimeoutError::Timeout) => {}
+                // the work finished, or panicked and dropped the sender while unwinding
+                Ok(()) | Err(RecvTimeoutError::Disconnected) => return false,
+            }
+
+            // the caller's runtime is blocked on the work, so the connections it opened are not
+            // driven any more and the provider is detached from them
+            let detached = provider.detached().unwrap_or_else(|e| {
+                error!(logger, "the watchdog failed to detach the provider"; "error"=>%e);
+                None
+            });
+            let provider = detached.as_ref().unwrap_or(provider);
+            match tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+            {
+                Ok(rt) => rt.block_on(terminate(provider, logger, instance_ids)),
+                Err(e) => {
+                    let e = Error::io("start a runtime for the watchdog", e);
+                    crit!(logger, "the watchdog failed to terminate the instances"; "error"=>%e);
+                }
+            }
+            true
+        });
+
+        let result = work();
+        drop(finished);
+        (result, expired.join().unwrap_or(true))
+    })
+}
+
+///Like [`run_until`] for async work. The watchdog is a timer polled alongside the work, so the
+///work must not block the runtime, or the deadline is only noticed once it yields. Past the
+///deadline the work is dropped without being polled again, and fails with [`Error::Deadline`]
+///for the given maximum duration.
+pub(crate) async fn run_until_async<P, T>(
+    provider: &P,
+    logger: &Logger,
+    instance_ids: &[String],
+    deadline: Instant,
+    max_duration: Duration,
+    work: impl Future<Output = Result<T, Error>>,
+) -> (Result<T, Error>, bool)
+where
+    P: Provider,
+{
+    tokio::select! {
+        result = work => return (result, false),
+        _ = time::sleep_until(deadline.into()) => {}
+    }
+
+    terminate(provider, logger, instance_ids).await;
+    (Err(Error::Deadline(max_duration)), true)
+}
+
+async fn terminate<P: Provider>(provider: &P, logger: &Logger, instance_ids: &[String]) {
+    crit!(logger, "the burst reached its deadline, terminating the instances"; "instances"=>?instance_ids);
+    if instance_ids.is_empty() {
+        return;
+    }
+    if let Err(e) = provider.terminate_instances(instance_ids).await {
+        crit!(logger, "the watchdog failed to terminate the instances"; "error"=>?e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::run_until;
+    use crate::Error;
+    use crate::provider::{Ec2, Fake, Provider};
+    use crate::testing::{builder, run_torn_down};
+    use slog::{Discard, Logger, o};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc::{self, Receiver};
+    use std::time::{Duration, Instant};
+
+    //An endpoint which keeps its connections open and answers every request with an error. A
+    //message is sent for each request.
+    fn endpoint() -> (String, Receiver<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let (sender, requests) = mpsc::channel();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let sender = sender.clone();
+                let mut reader = BufReader::new(stream.unwrap());
+                std::thread::spawn(move || {
+                    loop {
+                        let mut length = 0;
+                        let mut line = String::new();
+                        // the head ends with an empty line
+                        while {
+                            line.clear();
+                            reader.read_line(&mut line).unwrap() > 2
+                        } {
+                            let lower = line.to_ascii_lowercase();
+                            if let Some(value) = lower.strip_prefix("content-length:") {
+                                length = value.trim().parse().unwrap();
+                            }
+                        }
+                        if line.is_empty() {
+                            return;
+                        }
+                        reader.read_exact(&mut vec![0; length]).unwrap();
+                        reader
+                            .get_mut()
+                            .write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n")
+                            .unwrap();
+                        let _ = sender.send(());
+                    }
+                });
+            }
+        });
+        (endpoint, requests)
+    }
+
+    #[test]
+    fn deadline_is_not_held_up_by_the_blocked_runtime() {
+        let (endpoint, requests) = endpoint();
+        let region = rusoto_core::Region::Custom {
+            name: "eu-local-1".to_string(),
+            endpoint,
+        };
+        let credentials = crate::credential::StaticProvider::new_minimal(
+            "AKIDSTATIC".to_string(),
+            "secret".to_string(),
+        );
+        let ec2 = Ec2::with_credentials(region, credentials).unwrap();
+        let logger = Logger::root(Discard, o!());
+        let instance_ids = ["i-1".to_string()];
+
+        // the watchdog fires while the work blocks the only thread of the runtime which drives
+        // the connection the client pooled
+        let (finished, watchdog) = mpsc::channel();
+        std::thread::spawn(move || {
+            let rt = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            rt.block_on(async {
+                assert!(ec2.terminate_instances(&instance_ids).await.is_err());
+                requests.recv().unwrap();
+                let (terminated, expired) =
+                    run_until(&ec2, &logger, &instance_ids, Instant::now(), || {
+                        requests.recv_timeout(Duration::from_secs(10))
+                    });
+                finished.send(terminated.is_ok() && expired).unwrap();
+            });
+        });
+
+        assert!(watchdog.recv_timeout(Duration::from_secs(20)).unwrap());
+    }
+
+    #[test]
+    fn deadline_terminates_the_cluster() {
+        let mut builder = builder(&[("server", 1)]);
+        builder.set_max_duration(Duration::from_millis(500));
+        let fake = Fake::new();
+
+        let result = run_torn_down(
+            &fake,
+            builder.run_with(&fake, |vms| {
+                // a benchmark which would run forever if its machine stayed around
+                loop {
+                    vms["server"][0].run("sleep 0.1")?;
+                }
+            }),
+        );
+
+        assert!(result.is_err());
+        assert_eq!(
+            fake.scheduled_shutdowns().values().collect::<Vec<_>>(),
+            [&1]
+        );
+    }
+
+    #[test]
+    fn deadline_terminates_the_cluster_of_an_async_script() {
+        let mut builder = builder(&[("server", 1)]);
+        builder.set_max_duration(Duration::from_millis(500));
+        let fake = Fake::new();
+
+        let error = run_torn_down(
+            &fake,
+            builder.run_with_async(&fake, |vms| async move {
+                loop {
+                    vms["server"][0].run_async("sleep 0.1").await?;
+                }
+            }),
+        )
+        .unwrap_err();
+
+        assert!(matches!(error, Error::Deadline(_)));
+    }
+
+    #[test]
+    fn deadline_aborts_an_async_script_waiting_on_something_else() {
+        let mut builder = builder(&[("server", 1)]);
+        builder.set_max_duration(Duration::from_millis(500));
+        let fake = Fake::new();
+
+        let error = run_torn_down(
+            &fake,
+            builder.run_with_async(&fake, |_| std::future::pending()),
+        )
+        .unwrap_err();
+
+        assert!(matches!(error, Error::Deadline(_)));
+    }
+}